@@ -0,0 +1,183 @@
+//! `rust-runner bench` によるプログラムの実行時間の計測。
+//!
+//! プロファイルの指定がなければ release で一度だけビルドし、ウォームアップの後に指定した回数だけ実行して、実時間の統計と
+//! 最大の常駐メモリ量 (peak RSS) を表示する。標準入力は最初にすべて読み込み、毎回同じ内容を与える。
+//! プログラムの出力は捨てる。
+
+use crate::config::Config;
+use crate::limit::{Limits, Termination};
+use crate::{Args, Context};
+use failure::{bail, Fallible};
+use itertools::Itertools;
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::Builder;
+
+const USAGE: &str = "usage: rust-runner bench [--runs <n>] [--warmup <n>] [--json] [<flags>...] <source> [<args>...]";
+
+/// 一回の実行の計測結果。
+struct Measurement {
+    wall_time: Duration,
+    /// 最大の常駐メモリ量 (バイト)。計測できない環境では `None` になる。
+    max_rss: Option<u64>,
+}
+
+/// `rust-runner bench` を実行する。
+pub fn command(args: &[&str], mut config: Config) -> Fallible<i32> {
+    let mut runs = 10;
+    let mut warmup = 1;
+    let mut json = false;
+    let mut rest = args;
+    loop {
+        match rest {
+            ["--runs", n, tail @ ..] => {
+                runs = parse_count(n)?;
+                rest = tail;
+            }
+            ["--warmup", n, tail @ ..] => {
+                warmup = parse_count(n)?;
+                rest = tail;
+            }
+            ["--json", tail @ ..] => {
+                json = true;
+                rest = tail;
+            }
+            _ => break,
+        }
+    }
+    if runs == 0 {
+        bail!("--runs must be at least 1.");
+    }
+
+    // 残りは通常の実行と同じようにパースする
+    let args = Args::parse_timing_subcommand_args("bench", rest, &mut config)?;
+    // 標準入力はプログラムへの入力に使うので、ソースコードは標準入力から読めない
+    if let crate::SourceFile::Stdin = args.source_file {
+        bail!("a source file is required.\n{}", USAGE);
+    }
+
+    let content = args.source_file.read_content()?;
+    let context = Context::parse(
+        &content,
+        &args.source_file.base_dir()?,
+        &config,
+        &args.options,
+    )?;
+    let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+    let binary = crate::prepare_binary(tmpdir.path(), &content, &context, args.no_cache)?;
+
+    let input = crate::read_program_input()?;
+
+    for _ in 0..warmup {
+        run_once(&binary, &args.program_args, &input, &context.limits)?;
+    }
+    let measurements = (0..runs)
+        .map(|_| run_once(&binary, &args.program_args, &input, &context.limits))
+        .collect::<Fallible<Vec<_>>>()?;
+
+    let stats = Stats::new(measurements.iter().map(|m| m.wall_time.as_secs_f64()));
+    let peak_rss = measurements.iter().filter_map(|m| m.max_rss).max();
+    if json {
+        let report = json!({
+            "runs": runs,
+            "warmup": warmup,
+            "wall_time_secs": {
+                "min": stats.min,
+                "median": stats.median,
+                "mean": stats.mean,
+                "stddev": stats.stddev,
+            },
+            "peak_rss_bytes": peak_rss,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("runs:     {} (warmup: {})", runs, warmup);
+        println!(
+            "time:     min {}, median {}, mean {}, stddev {}",
+            format_secs(stats.min),
+            format_secs(stats.median),
+            format_secs(stats.mean),
+            format_secs(stats.stddev)
+        );
+        match peak_rss {
+            Some(rss) => println!("peak rss: {:.1} MiB", rss as f64 / (1024.0 * 1024.0)),
+            None => println!("peak rss: unavailable"),
+        }
+    }
+
+    Ok(0)
+}
+
+fn parse_count(n: &str) -> Fallible<usize> {
+    match n.parse() {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("invalid count: {}", n),
+    }
+}
+
+/// プログラムを一回実行して計測する。プログラムが失敗した場合はエラーにする。
+fn run_once(
+    binary: &Path,
+    args: &[String],
+    input: &[u8],
+    limits: &Limits,
+) -> Fallible<Measurement> {
+    let (outcome, _, _) = crate::run_with_input(binary, args, input, limits)?;
+    match outcome.termination {
+        Termination::Exited(status) if status.success() => {}
+        termination => bail!("the program failed: {}.", termination),
+    }
+
+    Ok(Measurement {
+        wall_time: outcome.elapsed,
+        max_rss: outcome.max_rss,
+    })
+}
+
+/// 実行時間 (秒) の統計。
+struct Stats {
+    min: f64,
+    median: f64,
+    mean: f64,
+    /// 標本標準偏差。一回しか実行していない場合は 0 とする。
+    stddev: f64,
+}
+
+impl Stats {
+    fn new(samples: impl Iterator<Item = f64>) -> Stats {
+        let samples = samples.sorted_by(|a, b| a.total_cmp(b)).collect_vec();
+        let n = samples.len();
+        let min = samples[0];
+        let median = if n % 2 == 0 {
+            (samples[n / 2 - 1] + samples[n / 2]) / 2.0
+        } else {
+            samples[n / 2]
+        };
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let stddev = if n > 1 {
+            let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            variance.sqrt()
+        } else {
+            0.0
+        };
+
+        Stats {
+            min,
+            median,
+            mean,
+            stddev,
+        }
+    }
+}
+
+/// 秒数を見やすい単位で表示する。
+fn format_secs(secs: f64) -> String {
+    if secs >= 1.0 {
+        format!("{:.3} s", secs)
+    } else if secs >= 1e-3 {
+        format!("{:.3} ms", secs * 1e3)
+    } else {
+        format!("{:.3} us", secs * 1e6)
+    }
+}
@@ -0,0 +1,769 @@
+//! `rust-runner bundle` による提出用の単一ファイルの生成。
+//!
+//! ジャッジは一つのファイルしか受け付けないので、スクリプトが参照しているローカルのライブラリクレートを
+//! 展開し、スクリプトの後ろに入れ子の `mod` として付け加える。展開するライブラリは設定ファイルの
+//! `[bundle.libraries]` で指定する。
+//!
+//! ライブラリは `mod foo;` で宣言されたファイルもすべて読み込み、一つのモジュールにまとめる。その際、
+//! `#[cfg(test)]` と `#[test]` のアイテムと、スクリプトから直接にも間接的にも参照されていないライブラリ直下の
+//! アイテムやモジュールは取り除く。参照の解析はトークン列を見るだけの大まかなものなので、迷った場合は
+//! 残す側に倒す。
+//!
+//! ライブラリの中の `crate::` と `$crate::` は展開先のモジュールを指すように書き換える。`#[macro_export]`
+//! したマクロはクレートの直下ではなくライブラリのモジュールから参照できるよう、`pub(crate) use` で公開する。
+//! スクリプトの中の `foo::` と `::foo::` で始まるパスは、スクリプトの中のモジュールからも展開したライブラリを
+//! 参照できるよう、`crate::foo::` に書き換える。
+//!
+//! 生成したコードは、設定ファイルの `allowed-crates` に挙げたクレートだけを依存クレートとしてビルドできるか
+//! 確かめてから出力する。
+
+use crate::config::Config;
+use crate::manifest;
+use crate::{Args, Context};
+use failure::{bail, Fallible};
+use proc_macro2::{Delimiter, Group, Ident, Punct, Spacing, Span, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::prelude::*;
+use std::io::stdout;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use syn::ext::IdentExt;
+use syn::{parse_quote, Attribute, Expr, Item, ItemMod, Lit, Meta, UseTree};
+use tempfile::Builder;
+
+const USAGE: &str = "usage: rust-runner bundle [-o <output>] [--no-verify] [<flags>...] <source>";
+
+/// 参照の解析で、すべてのアイテムを指すものとして扱う名前。
+const ALL: &str = "*";
+
+/// `rust-runner bundle` を実行する。
+pub fn command(args: &[&str], mut config: Config) -> Fallible<i32> {
+    let mut output = None;
+    let mut verify = true;
+    let mut rest = args;
+    loop {
+        match rest {
+            ["-o", path, tail @ ..] | ["--output", path, tail @ ..] => {
+                output = Some(PathBuf::from(path));
+                rest = tail;
+            }
+            ["--no-verify", tail @ ..] => {
+                verify = false;
+                rest = tail;
+            }
+            _ => break,
+        }
+    }
+
+    // 残りは通常の実行と同じようにパースする。プロファイルなどの指定は確認のためのビルドに使う。
+    let args = Args::parse_subcommand_args("bundle", rest, &mut config)?;
+    if !args.program_args.is_empty() {
+        bail!(
+            "unexpected arguments: {}\n{}",
+            args.program_args.join(" "),
+            USAGE
+        );
+    }
+
+    let content = args.source_file.read_content()?;
+    let bundled = bundle(&content, &config.bundle.libraries)?;
+    if verify {
+        verify_bundle(&bundled, &args, &config)?;
+    }
+
+    match output {
+        Some(path) => fs::write(path, &bundled)?,
+        None => stdout().write_all(bundled.as_bytes())?,
+    }
+
+    Ok(0)
+}
+
+/// スクリプトに、参照しているライブラリを展開したモジュールを付け加える。
+fn bundle(content: &str, libraries: &BTreeMap<String, PathBuf>) -> Fallible<String> {
+    // シバンとフロントマターは提出するコードには不要なので取り除く
+    let (script, _) = manifest::split_frontmatter(&crate::blank_shebang(content))?;
+    let tokens: TokenStream = match script.parse() {
+        Ok(tokens) => tokens,
+        Err(e) => bail!("failed to parse the script: {}", e),
+    };
+
+    let mut modules = Vec::new();
+    for (name, path) in libraries {
+        let mut requested = BTreeSet::new();
+        collect_path_refs(tokens.clone(), &[name], &mut requested);
+        if requested.is_empty() {
+            continue;
+        }
+
+        modules.push((name.as_str(), bundle_library(name, path, requested)?));
+    }
+
+    let names: Vec<&str> = modules.iter().map(|(name, _)| *name).collect();
+    let script = rewrite_script_paths(&script, tokens, &names);
+    let mut bundled = script.trim_start_matches('\n').to_string();
+    for (_, module) in modules {
+        if !bundled.ends_with('\n') {
+            bundled.push('\n');
+        }
+        bundled.push('\n');
+        bundled.push_str(&module);
+    }
+
+    Ok(bundled)
+}
+
+/// ライブラリを読み込み、`requested` の名前とそれらが参照しているものだけを残したモジュールのコードを返す。
+fn bundle_library(name: &str, path: &Path, requested: BTreeSet<String>) -> Fallible<String> {
+    let root = if path.is_file() {
+        path.to_path_buf()
+    } else {
+        path.join("src").join("lib.rs")
+    };
+    let (attrs, items) = load_file(&root, root.parent().unwrap_or(Path::new(".")))?;
+    let items = select_items(items, requested);
+
+    // クレートの直下に公開されていたマクロを、ライブラリのモジュールの直下から参照できるようにする
+    let mut exports = Vec::new();
+    let mut items = export_macros(items, &[], &mut exports);
+    for (module_path, ident) in exports.iter().rev() {
+        if module_path.is_empty() {
+            continue;
+        }
+        let path: syn::Path = syn::parse_str(&format!("self::{}", module_path.join("::")))?;
+        items.insert(
+            0,
+            parse_quote!(#[allow(unused_imports)] pub(crate) use #path::#ident;),
+        );
+    }
+
+    // クレート全体に対する属性はモジュールには付けられないので、lint の設定だけを引き継ぐ
+    let attrs = attrs.iter().filter(|attr| attr.path().is_ident("allow"));
+    let ident = Ident::new(name, Span::call_site());
+    let tokens = quote! {
+        mod #ident {
+            #(#attrs)*
+            #(#items)*
+        }
+    };
+    let file: syn::File = match syn::parse2(rewrite_crate_paths(tokens, name)) {
+        Ok(file) => file,
+        Err(e) => bail!("failed to bundle `{}`: {}", name, e),
+    };
+
+    Ok(prettyplease::unparse(&file))
+}
+
+/// ソースファイルを読み込み、クレート (またはモジュール) の内部属性とアイテムを返す。`dir` はこのファイルの
+/// 中で宣言されたモジュールのファイルを探すディレクトリ。
+fn load_file(path: &Path, dir: &Path) -> Fallible<(Vec<Attribute>, Vec<Item>)> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => bail!("failed to read {}: {}", path.display(), e),
+    };
+    let file = match syn::parse_file(&content) {
+        Ok(file) => file,
+        Err(e) => bail!("failed to parse {}: {}", path.display(), e),
+    };
+    let items = load_items(file.items, dir)?;
+
+    Ok((file.attrs, items))
+}
+
+/// テストのためのアイテムを取り除き、`mod foo;` をファイルの中身で置き換える。
+fn load_items(items: Vec<Item>, dir: &Path) -> Fallible<Vec<Item>> {
+    let mut loaded = Vec::new();
+    for item in items {
+        if is_test_only(attrs_of(&item)) {
+            continue;
+        }
+
+        match item {
+            Item::Mod(mut module) => {
+                let child_dir = dir.join(module.ident.unraw().to_string());
+                module.content = match module.content.take() {
+                    Some((brace, items)) => Some((brace, load_items(items, &child_dir)?)),
+                    None => {
+                        let (path, child_dir) = module_file(&module, dir)?;
+                        let (attrs, items) = load_file(&path, &child_dir)?;
+                        module.attrs.retain(|attr| !attr.path().is_ident("path"));
+                        module.attrs.extend(attrs);
+                        module.semi = None;
+                        Some((Default::default(), items))
+                    }
+                };
+                loaded.push(Item::Mod(module));
+            }
+            item => loaded.push(item),
+        }
+    }
+
+    Ok(loaded)
+}
+
+/// `mod foo;` の中身のファイルと、そのファイルの中で宣言されたモジュールを探すディレクトリを返す。
+fn module_file(module: &ItemMod, dir: &Path) -> Fallible<(PathBuf, PathBuf)> {
+    // `#[path = "..."]` で指定されている場合はそのファイルを使う
+    let path_attr = module.attrs.iter().find_map(|attr| match &attr.meta {
+        Meta::NameValue(meta) if meta.path.is_ident("path") => match &meta.value {
+            Expr::Lit(expr) => match &expr.lit {
+                Lit::Str(path) => Some(path.value()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    });
+    if let Some(path) = path_attr {
+        let path = dir.join(path);
+        let child_dir = path.parent().unwrap_or(dir).to_path_buf();
+        return Ok((path, child_dir));
+    }
+
+    let name = module.ident.unraw().to_string();
+    let child_dir = dir.join(&name);
+    let file = dir.join(format!("{}.rs", name));
+    if file.is_file() {
+        return Ok((file, child_dir));
+    }
+    let mod_rs = child_dir.join("mod.rs");
+    if mod_rs.is_file() {
+        return Ok((mod_rs, child_dir));
+    }
+
+    bail!(
+        "cannot find the file of module `{}`: neither {} nor {} exists.",
+        name,
+        file.display(),
+        mod_rs.display()
+    )
+}
+
+/// `#[cfg(test)]` または `#[test]` が付いているか。
+fn is_test_only(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| match &attr.meta {
+        Meta::Path(path) => path.is_ident("test"),
+        Meta::List(list) => list.path.is_ident("cfg") && list.tokens.to_string() == "test",
+        Meta::NameValue(_) => false,
+    })
+}
+
+fn attrs_of(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Const(item) => &item.attrs,
+        Item::Enum(item) => &item.attrs,
+        Item::ExternCrate(item) => &item.attrs,
+        Item::Fn(item) => &item.attrs,
+        Item::ForeignMod(item) => &item.attrs,
+        Item::Impl(item) => &item.attrs,
+        Item::Macro(item) => &item.attrs,
+        Item::Mod(item) => &item.attrs,
+        Item::Static(item) => &item.attrs,
+        Item::Struct(item) => &item.attrs,
+        Item::Trait(item) => &item.attrs,
+        Item::TraitAlias(item) => &item.attrs,
+        Item::Type(item) => &item.attrs,
+        Item::Union(item) => &item.attrs,
+        Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+/// ライブラリ直下のアイテムのうち、`requested` の名前を定義しているものと、それらが参照しているものを残す。
+fn select_items(items: Vec<Item>, requested: BTreeSet<String>) -> Vec<Item> {
+    // 名前から、それを定義しているアイテムへの対応表。名前を持たないアイテムは常に残す。
+    let mut definitions: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    let mut queue = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let names = defined_names(item);
+        if names.is_empty() {
+            queue.push(i);
+        }
+        for name in names {
+            definitions.entry(name).or_default().push(i);
+        }
+    }
+
+    let mut selected = vec![false; items.len()];
+    let mut pending: Vec<String> = requested.into_iter().collect();
+    while !pending.is_empty() || !queue.is_empty() {
+        for name in pending.drain(..) {
+            match definitions.get(&name) {
+                _ if name == ALL => queue.extend(0..items.len()),
+                Some(indices) => queue.extend(indices),
+                // 見つからない名前はグロブで再エクスポートされているかもしれない
+                None => queue.extend(definitions.get(ALL).into_iter().flatten()),
+            }
+        }
+        while let Some(i) = queue.pop() {
+            if !selected[i] {
+                selected[i] = true;
+                pending.extend(referenced_names(&items[i], &definitions));
+            }
+        }
+    }
+
+    items
+        .into_iter()
+        .zip(selected)
+        .filter_map(|(item, selected)| if selected { Some(item) } else { None })
+        .collect()
+}
+
+/// ライブラリ直下のアイテムが定義する名前。`impl` はその型とトレイトの名前を定義するものとみなし、
+/// モジュールはその中で `#[macro_export]` したマクロの名前も定義するものとみなす。
+fn defined_names(item: &Item) -> Vec<String> {
+    let ident = |ident: &Ident| vec![ident.unraw().to_string()];
+    match item {
+        Item::Const(item) => ident(&item.ident),
+        Item::Enum(item) => ident(&item.ident),
+        Item::Fn(item) => ident(&item.sig.ident),
+        Item::Static(item) => ident(&item.ident),
+        Item::Struct(item) => ident(&item.ident),
+        Item::Trait(item) => ident(&item.ident),
+        Item::TraitAlias(item) => ident(&item.ident),
+        Item::Type(item) => ident(&item.ident),
+        Item::Union(item) => ident(&item.ident),
+        Item::Macro(item) => item.ident.iter().flat_map(ident).collect(),
+        Item::Mod(item) => {
+            let mut names = ident(&item.ident);
+            if let Some((_, items)) = &item.content {
+                collect_exported_macros(items, &mut names);
+            }
+            names
+        }
+        Item::Impl(item) => {
+            let mut names = Vec::new();
+            collect_idents(item.self_ty.to_token_stream(), &mut names);
+            if let Some((_, path, _)) = &item.trait_ {
+                names.extend(
+                    path.segments
+                        .last()
+                        .map(|segment| segment.ident.to_string()),
+                );
+            }
+            names
+        }
+        Item::Use(item) => {
+            let mut names = Vec::new();
+            collect_use_names(&item.tree, &mut names);
+            names
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// アイテムが参照している、ライブラリ直下の名前。
+///
+/// モジュールの中からは `crate::foo` や `super::foo` の形でしか参照できないので、その形のパスとマクロの
+/// 呼び出しだけを見る。それ以外のアイテムは、ライブラリ直下で定義された名前と同じ識別子をすべて参照と
+/// みなす。
+fn referenced_names(item: &Item, definitions: &BTreeMap<String, Vec<usize>>) -> Vec<String> {
+    let tokens = item.to_token_stream();
+    let mut refs = BTreeSet::new();
+    match item {
+        Item::Mod(_) => {
+            collect_path_refs(tokens.clone(), &["crate", "super"], &mut refs);
+            let mut macros = Vec::new();
+            collect_macro_calls(tokens, &mut macros);
+            refs.extend(
+                macros
+                    .into_iter()
+                    .filter(|name| definitions.contains_key(name)),
+            );
+        }
+        _ => {
+            collect_path_refs(tokens.clone(), &["crate", "self"], &mut refs);
+            let mut idents = Vec::new();
+            collect_idents(tokens, &mut idents);
+            refs.extend(
+                idents
+                    .into_iter()
+                    .filter(|name| name != ALL && definitions.contains_key(name)),
+            );
+        }
+    }
+
+    refs.into_iter().collect()
+}
+
+fn collect_exported_macros(items: &[Item], names: &mut Vec<String>) {
+    for item in items {
+        match item {
+            Item::Macro(item) if is_macro_export(&item.attrs) => {
+                names.extend(item.ident.iter().map(|ident| ident.to_string()));
+            }
+            Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    collect_exported_macros(items, names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_macro_export(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("macro_export"))
+}
+
+/// `use` ツリーが導入する名前。グロブは `ALL` とする。
+fn collect_use_names(tree: &UseTree, names: &mut Vec<String>) {
+    match tree {
+        UseTree::Path(path) => collect_use_names(&path.tree, names),
+        UseTree::Name(name) => names.push(name.ident.unraw().to_string()),
+        UseTree::Rename(rename) => names.push(rename.rename.unraw().to_string()),
+        UseTree::Glob(_) => names.push(ALL.to_string()),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                collect_use_names(tree, names);
+            }
+        }
+    }
+}
+
+/// トークン列に含まれる識別子をすべて集める。
+fn collect_idents(tokens: TokenStream, idents: &mut Vec<String>) {
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => collect_idents(group.stream(), idents),
+            TokenTree::Ident(ident) => idents.push(ident.unraw().to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// `foo!` の形のマクロの呼び出しの名前を集める。
+fn collect_macro_calls(tokens: TokenStream, macros: &mut Vec<String>) {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        match (token, tokens.get(i + 1)) {
+            (TokenTree::Group(group), _) => collect_macro_calls(group.stream(), macros),
+            (TokenTree::Ident(ident), Some(TokenTree::Punct(punct))) if punct.as_char() == '!' => {
+                macros.push(ident.unraw().to_string())
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `prefix::name` の形のパスの `name` を集める。`prefix::{a, b::c}` なら `a` と `b` を、`prefix::*` なら
+/// `ALL` を集める。
+fn collect_path_refs(tokens: TokenStream, prefixes: &[&str], refs: &mut BTreeSet<String>) {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Group(group) => {
+                collect_path_refs(group.stream(), prefixes, refs);
+                continue;
+            }
+            TokenTree::Ident(ident) if prefixes.iter().any(|prefix| ident == prefix) => {}
+            _ => continue,
+        }
+
+        match &tokens[i + 1..] {
+            [TokenTree::Punct(colon1), TokenTree::Punct(colon2), next, ..]
+                if is_path_sep(colon1, colon2) =>
+            {
+                match next {
+                    TokenTree::Ident(name) if !is_path_keyword(name) => {
+                        refs.insert(name.unraw().to_string());
+                    }
+                    TokenTree::Punct(punct) if punct.as_char() == '*' => {
+                        refs.insert(ALL.to_string());
+                    }
+                    TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                        collect_use_group_roots(group.stream(), refs)
+                    }
+                    _ => {}
+                }
+            }
+            // 別名を付けて使われると参照先を追えないので、すべて参照されているものとみなす
+            [TokenTree::Ident(next), ..] if next == "as" => {
+                refs.insert(ALL.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `{a, b::c, *}` の形の `use` ツリーのグループから、各要素の先頭の名前を集める。
+fn collect_use_group_roots(tokens: TokenStream, refs: &mut BTreeSet<String>) {
+    let mut at_root = true;
+    for token in tokens {
+        match token {
+            TokenTree::Punct(punct) => match punct.as_char() {
+                ',' => at_root = true,
+                '*' if at_root => {
+                    refs.insert(ALL.to_string());
+                }
+                _ => {}
+            },
+            TokenTree::Ident(ident) => {
+                if at_root && !is_path_keyword(&ident) {
+                    refs.insert(ident.unraw().to_string());
+                }
+                at_root = false;
+            }
+            TokenTree::Group(_) | TokenTree::Literal(_) => at_root = false,
+        }
+    }
+}
+
+fn is_path_sep(colon1: &Punct, colon2: &Punct) -> bool {
+    colon1.as_char() == ':' && colon1.spacing() == Spacing::Joint && colon2.as_char() == ':'
+}
+
+fn is_path_keyword(ident: &Ident) -> bool {
+    ident == "self" || ident == "super" || ident == "crate"
+}
+
+/// `#[macro_export]` したマクロの属性を取り除き、代わりに定義したモジュールで `pub(crate) use` する。
+/// 取り除いたマクロを、定義したモジュールのパスとともに `exports` に記録する。
+fn export_macros(
+    items: Vec<Item>,
+    module_path: &[String],
+    exports: &mut Vec<(Vec<String>, Ident)>,
+) -> Vec<Item> {
+    let mut exported = Vec::new();
+    for item in items {
+        match item {
+            Item::Macro(mut item) if item.ident.is_some() && is_macro_export(&item.attrs) => {
+                item.attrs
+                    .retain(|attr| !attr.path().is_ident("macro_export"));
+                let ident = item.ident.clone().unwrap();
+                exported.push(Item::Macro(item));
+                exported.push(parse_quote!(#[allow(unused_imports)] pub(crate) use #ident;));
+                exports.push((module_path.to_vec(), ident));
+            }
+            Item::Mod(mut item) => {
+                if let Some((brace, items)) = item.content.take() {
+                    let mut path = module_path.to_vec();
+                    path.push(item.ident.to_string());
+                    item.content = Some((brace, export_macros(items, &path, exports)));
+                }
+                exported.push(Item::Mod(item));
+            }
+            item => exported.push(item),
+        }
+    }
+
+    exported
+}
+
+/// `crate::` と `$crate::` で始まるパスを、ライブラリを展開したモジュールから始まるように書き換える。
+fn rewrite_crate_paths(tokens: TokenStream, library: &str) -> TokenStream {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    let mut rewritten = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Group(group) => {
+                let mut new_group = Group::new(
+                    group.delimiter(),
+                    rewrite_crate_paths(group.stream(), library),
+                );
+                new_group.set_span(group.span());
+                rewritten.push(TokenTree::Group(new_group));
+            }
+            TokenTree::Ident(ident) if ident == "crate" => {
+                rewritten.push(token.clone());
+                if let [TokenTree::Punct(colon1), TokenTree::Punct(colon2), ..] = &tokens[i + 1..] {
+                    if is_path_sep(colon1, colon2) {
+                        rewritten.push(TokenTree::Punct(Punct::new(':', Spacing::Joint)));
+                        rewritten.push(TokenTree::Punct(Punct::new(':', Spacing::Alone)));
+                        rewritten.push(TokenTree::Ident(Ident::new(library, ident.span())));
+                    }
+                }
+            }
+            token => rewritten.push(token.clone()),
+        }
+    }
+
+    rewritten.into_iter().collect()
+}
+
+/// スクリプトの中の `lib::` と `::lib::` で始まるパスを `crate::lib::` に書き換える。
+///
+/// 展開したライブラリはクレートの直下のモジュールになり、スクリプトの中のモジュールからはそのままでは
+/// 参照できない。コメントや書式を残すため、トークンの位置をもとに元のテキストを書き換える。
+fn rewrite_script_paths(script: &str, tokens: TokenStream, libraries: &[&str]) -> String {
+    let mut edits = Vec::new();
+    collect_script_path_edits(tokens, libraries, false, &mut edits);
+    edits.sort_by_key(|range| range.start);
+
+    let mut rewritten = String::new();
+    let mut last = 0;
+    for range in edits {
+        rewritten.push_str(&script[last..range.start]);
+        rewritten.push_str("crate::");
+        last = range.end;
+    }
+    rewritten.push_str(&script[last..]);
+
+    rewritten
+}
+
+/// `crate::` に置き換えるテキストの範囲を集める。`lib::` の前に挿入する場合は空の範囲、`::lib::` の場合は
+/// 先頭の `::` の範囲になる。
+///
+/// `in_use_group` は `a::{...}` のようにパスの途中にある `use` ツリーのグループの中か。その直下の要素は
+/// パスの先頭ではないので書き換えない。
+fn collect_script_path_edits(
+    tokens: TokenStream,
+    libraries: &[&str],
+    in_use_group: bool,
+    edits: &mut Vec<Range<usize>>,
+) {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        let ident = match token {
+            TokenTree::Group(group) => {
+                let in_use_group = group.delimiter() == Delimiter::Brace
+                    && matches!(
+                        &tokens[..i],
+                        [.., TokenTree::Punct(colon1), TokenTree::Punct(colon2)]
+                            if is_path_sep(colon1, colon2)
+                    );
+                collect_script_path_edits(group.stream(), libraries, in_use_group, edits);
+                continue;
+            }
+            TokenTree::Ident(ident) if libraries.iter().any(|library| ident == library) => ident,
+            _ => continue,
+        };
+        match &tokens[i + 1..] {
+            [TokenTree::Punct(colon1), TokenTree::Punct(colon2), ..]
+                if is_path_sep(colon1, colon2) => {}
+            _ => continue,
+        }
+
+        let start = ident.span().byte_range().start;
+        match &tokens[..i] {
+            // `a::lib::` や `<T>::lib::` はパスの途中なので書き換えない
+            [.., TokenTree::Ident(_), TokenTree::Punct(colon1), TokenTree::Punct(colon2)]
+                if is_path_sep(colon1, colon2) => {}
+            [.., TokenTree::Punct(angle), TokenTree::Punct(colon1), TokenTree::Punct(colon2)]
+                if angle.as_char() == '>' && is_path_sep(colon1, colon2) => {}
+            [.., TokenTree::Punct(colon1), TokenTree::Punct(colon2)]
+                if is_path_sep(colon1, colon2) =>
+            {
+                edits.push(colon1.span().byte_range().start..start)
+            }
+            // メソッドのターボフィッシュやマクロの変数は書き換えない
+            [.., TokenTree::Punct(punct)] if matches!(punct.as_char(), '.' | '$') => {}
+            _ if in_use_group => {}
+            _ => edits.push(start..start),
+        }
+    }
+}
+
+/// まとめたコードが、ジャッジで使えるクレートだけを依存クレートとしてビルドできるか確かめる。
+fn verify_bundle(bundled: &str, args: &Args, config: &Config) -> Fallible<()> {
+    let context = Context::parse(
+        bundled,
+        &args.source_file.base_dir()?,
+        config,
+        &args.options,
+    )?;
+    let unavailable: Vec<&str> = context
+        .dependencies
+        .keys()
+        .filter(|name| !config.bundle.allowed_crates.contains(name))
+        .map(String::as_str)
+        .collect();
+    if !unavailable.is_empty() {
+        bail!(
+            "the bundled code uses crates that are not available on the judge: {}. add them to `allowed-crates` in the [bundle] section of the config if the judge provides them.",
+            unavailable.join(", ")
+        );
+    }
+
+    let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+    crate::prepare_binary(tmpdir.path(), bundled, &context, args.no_cache)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{defined_names, rewrite_script_paths, select_items};
+    use proc_macro2::TokenStream;
+    use std::collections::BTreeSet;
+
+    fn rewrite(script: &str) -> String {
+        let tokens: TokenStream = script.parse().unwrap();
+        rewrite_script_paths(script, tokens, &["our_lib"])
+    }
+
+    fn select(library: &str, requested: &[&str]) -> Vec<String> {
+        let items = syn::parse_file(library).unwrap().items;
+        let requested: BTreeSet<String> = requested.iter().map(|name| name.to_string()).collect();
+        select_items(items, requested)
+            .iter()
+            .flat_map(defined_names)
+            .collect()
+    }
+
+    #[test]
+    fn rewrite_relative_and_absolute_paths() {
+        assert_eq!(
+            rewrite("fn main() { our_lib::f(); ::our_lib::g(); }"),
+            "fn main() { crate::our_lib::f(); crate::our_lib::g(); }"
+        );
+        assert_eq!(
+            rewrite("// our_lib::f\nmod m { fn f() { our_lib::f() } }"),
+            "// our_lib::f\nmod m { fn f() { crate::our_lib::f() } }"
+        );
+    }
+
+    #[test]
+    fn keep_paths_not_starting_with_library() {
+        for script in &[
+            "fn main() { a::our_lib::f(); }",
+            "fn main() { crate::our_lib::f(); }",
+            "fn main() { <T>::our_lib::f(); }",
+            "fn main() { let our_lib = 1; }",
+            "macro_rules! m { ($our_lib:ident) => { $our_lib::f() }; }",
+        ] {
+            assert_eq!(rewrite(script), *script);
+        }
+    }
+
+    #[test]
+    fn rewrite_use_groups() {
+        assert_eq!(
+            rewrite("use our_lib::{a, b::c};"),
+            "use crate::our_lib::{a, b::c};"
+        );
+        assert_eq!(
+            rewrite("use {our_lib::a, std::io};"),
+            "use {crate::our_lib::a, std::io};"
+        );
+        assert_eq!(rewrite("use a::{our_lib::b};"), "use a::{our_lib::b};");
+    }
+
+    #[test]
+    fn select_referenced_items() {
+        let library =
+            "pub fn a() { b() } fn b() {} pub fn unused() {} pub struct S; impl S { fn f() {} }";
+        assert_eq!(select(library, &["a"]), ["a", "b"]);
+        assert_eq!(select(library, &["S"]), ["S", "S"]);
+        assert_eq!(select(library, &["*"]), ["a", "b", "unused", "S", "S"]);
+    }
+
+    #[test]
+    fn select_items_referenced_from_nested_modules() {
+        let library = "
+            pub mod m { pub mod n { pub fn f() -> i32 { super::super::helper() } } }
+            pub mod k { pub fn g() -> i32 { crate::helper2() } }
+            fn helper() -> i32 { 0 }
+            fn helper2() -> i32 { 0 }
+            fn unused() {}
+        ";
+        assert_eq!(select(library, &["m"]), ["m", "helper"]);
+        assert_eq!(select(library, &["k"]), ["k", "helper2"]);
+    }
+}
@@ -0,0 +1,222 @@
+//! ビルド済みの実行ファイルと、依存クレートのビルド成果物を保存しておくキャッシュ。
+//!
+//! ソースコード・解決済みの `Context` (実行時の制限を除く)・rustc のバージョンから計算したハッシュをキーとして、
+//! `$XDG_CACHE_HOME/rust-runner/bin/<キー>` に実行ファイルを保存する。キーが一致すれば同じ実行ファイルが
+//! できるはずなので、プロジェクトの作成やビルドを省略してそのまま実行できる。
+//!
+//! また、ツールチェインごとに共有のターゲットディレクトリ `$XDG_CACHE_HOME/rust-runner/target/<ツールチェイン>`
+//! を用意し、すべてのプロジェクトのビルドに使う。同じバージョンの同じクレートを使うスクリプト同士で
+//! 依存クレートのビルド成果物を再利用できる。
+
+use crate::limit::Limits;
+use crate::toolchain;
+use crate::Context;
+use failure::{bail, Fallible};
+use filetime::FileTime;
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+/// `cache gc` で、これより長い間使われていないキャッシュを削除する既定の日数。
+const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+
+pub struct Cache {
+    root: PathBuf,
+}
+
+impl Cache {
+    /// キャッシュディレクトリを開く。存在しなければ作成する。
+    pub fn open() -> Fallible<Cache> {
+        let root = match dirs::cache_dir() {
+            Some(dir) => dir.join("rust-runner"),
+            None => bail!("failed to determine the cache directory."),
+        };
+        fs::create_dir_all(root.join("bin"))?;
+        fs::create_dir_all(root.join("target"))?;
+
+        Ok(Cache { root })
+    }
+
+    /// キーに対応するビルド済みの実行ファイルがあればそのパスを返す。
+    pub fn lookup(&self, key: &str) -> Option<PathBuf> {
+        let path = self.binary_path(key);
+        if !path.is_file() {
+            return None;
+        }
+
+        // `cache gc` で最近使われたものを残せるよう、最終使用日時として更新日時を記録しておく
+        let _ = filetime::set_file_mtime(&path, FileTime::now());
+
+        Some(path)
+    }
+
+    /// ビルドした実行ファイルをキャッシュへコピーし、コピー先のパスを返す。
+    pub fn store(&self, key: &str, binary: &Path) -> Fallible<PathBuf> {
+        // 同時に実行された別の rust-runner が中途半端なファイルを読まないよう、
+        // 一旦別名でコピーしてからリネームする
+        let path = self.binary_path(key);
+        let tmp_path = path.with_extension(format!("tmp{}", process::id()));
+        fs::copy(binary, &tmp_path)?;
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(path)
+    }
+
+    /// ツールチェインの共有ターゲットディレクトリをロックする。
+    ///
+    /// 同じディレクトリを使う他のビルドや `cache gc` とはロックで排他される。ビルドした実行ファイルは
+    /// 他のビルドで上書きされうるので、ロックを手放す前にターゲットディレクトリの外へ取り出すこと。
+    pub fn lock_target_dir(&self, toolchain: &str) -> Fallible<TargetDir> {
+        let name = sanitize_toolchain(toolchain);
+        let lock_path = self.root.join("target").join(format!("{}.lock", name));
+        let lock = lock_exclusive(&lock_path)?;
+        filetime::set_file_mtime(&lock_path, FileTime::now())?;
+
+        Ok(TargetDir {
+            path: self.root.join("target").join(name),
+            _lock: lock,
+        })
+    }
+
+    /// しばらく使われていないキャッシュを削除する。
+    ///
+    /// `max_age` より長い間使われていない実行ファイルと、ツールチェインごとの共有ターゲットディレクトリを
+    /// 削除する。`max_age` が `None` のときはすべて削除する。
+    pub fn gc(&self, max_age: Option<Duration>) -> Fallible<()> {
+        let is_stale = |path: &Path| -> Fallible<bool> {
+            let max_age = match max_age {
+                Some(max_age) => max_age,
+                None => return Ok(true),
+            };
+            let modified = fs::metadata(path)?.modified()?;
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            Ok(age > max_age)
+        };
+
+        for entry in fs::read_dir(self.root.join("bin"))? {
+            let path = entry?.path();
+            if is_stale(&path)? {
+                eprintln!("removing {}", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+
+        // ロックファイルの更新日時をそのツールチェインの最終使用日時とみなす
+        for entry in fs::read_dir(self.root.join("target"))? {
+            let lock_path = entry?.path();
+            if lock_path.extension().is_none_or(|ext| ext != "lock") || !is_stale(&lock_path)? {
+                continue;
+            }
+
+            // ビルド中のものは消さないよう、ロックを取ってから削除する
+            let _lock = lock_exclusive(&lock_path)?;
+            let target_dir = lock_path.with_extension("");
+            if target_dir.exists() {
+                eprintln!("removing {}", target_dir.display());
+                fs::remove_dir_all(&target_dir)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn binary_path(&self, key: &str) -> PathBuf {
+        self.root
+            .join("bin")
+            .join(format!("{}{}", key, env::consts::EXE_SUFFIX))
+    }
+}
+
+/// ロックされた共有ターゲットディレクトリ。スコープを抜けるとロックが解放される。
+pub struct TargetDir {
+    path: PathBuf,
+    _lock: File,
+}
+
+impl TargetDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// `rust-runner cache <subcommand>` を実行する。
+pub fn command(args: &[&str]) -> Fallible<i32> {
+    match args {
+        ["gc", rest @ ..] => {
+            let mut max_age = Some(Duration::from_secs(DEFAULT_MAX_AGE_DAYS * 24 * 60 * 60));
+            let mut rest = rest;
+            loop {
+                match rest {
+                    ["--max-age", days, tail @ ..] => {
+                        let days: u64 = match days.parse() {
+                            Ok(days) => days,
+                            Err(_) => bail!("invalid number of days: {}", days),
+                        };
+                        max_age = Some(Duration::from_secs(days * 24 * 60 * 60));
+                        rest = tail;
+                    }
+                    ["--all", tail @ ..] => {
+                        max_age = None;
+                        rest = tail;
+                    }
+                    [] => break,
+                    [arg, ..] => bail!("unknown argument for `cache gc`: {}", arg),
+                }
+            }
+
+            Cache::open()?.gc(max_age)?;
+            Ok(0)
+        }
+        [subcommand, ..] => bail!("unknown cache subcommand: {}", subcommand),
+        [] => bail!("usage: rust-runner cache gc [--max-age <days>] [--all]"),
+    }
+}
+
+/// ソースコードとその `Context` からキャッシュのキーを計算する。
+pub fn cache_key(content: &str, context: &Context) -> Fallible<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update([0]);
+    hasher.update(toolchain::rustc_version(&context.toolchain)?);
+    hasher.update([0]);
+    // 実行時の制限はビルド結果に影響しないので、変えても同じバイナリを使えるようにする
+    let context = Context {
+        limits: Limits::default(),
+        ..context.clone()
+    };
+    hasher.update(format!("{:?}", context));
+    hasher.update([0]);
+    hasher.update(content);
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// ファイルを作成して排他ロックを取る。
+fn lock_exclusive(path: &Path) -> Fallible<File> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.lock_exclusive()?;
+
+    Ok(file)
+}
+
+/// ツールチェイン名をディレクトリ名として使える形にする。
+fn sanitize_toolchain(toolchain: &str) -> String {
+    toolchain
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
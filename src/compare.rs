@@ -0,0 +1,136 @@
+//! `--toolchains` による複数のツールチェインでの実行結果の比較。
+//!
+//! ツールチェインごとに別々のプロジェクトを作り、並列にビルドして同じ入力で実行する。どのツールチェインで
+//! ビルドできたかと、それぞれの出力を表示し、出力が異なる場合は最初にビルドできたツールチェインとの差分を
+//! 表示する。MSRV の確認や、ツールチェインによる挙動の違いの調査に使う。
+
+use crate::config::Config;
+use crate::limit::Termination;
+use crate::{Args, Context, OptionType};
+use failure::Fallible;
+use similar::TextDiff;
+use std::io::prelude::*;
+use std::io::stdout;
+use std::thread;
+use tempfile::Builder;
+
+/// あるツールチェインで実行した結果。
+struct Outcome {
+    toolchain: String,
+    /// 実行できた場合は終了のしかたと標準出力、標準エラー出力。ビルドなどに失敗した場合はそのエラー。
+    result: Fallible<(Termination, Vec<u8>, Vec<u8>)>,
+}
+
+/// 各ツールチェインでビルドして実行し、結果を比較する。
+///
+/// すべてのツールチェインでビルドでき、終了コードと標準出力が一致した場合は 0 を、そうでなければ 1 を返す。
+pub fn run(args: &Args, config: &Config) -> Fallible<i32> {
+    let content = args.source_file.read_content()?;
+    let input = crate::read_program_input()?;
+
+    let outcomes: Vec<Outcome> = thread::scope(|scope| {
+        let handles: Vec<_> = args
+            .toolchains
+            .iter()
+            .map(|toolchain| {
+                let (content, input) = (&content, &input);
+                scope.spawn(move || Outcome {
+                    toolchain: toolchain.clone(),
+                    result: run_toolchain(toolchain, content, input, args, config),
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut stdout = stdout();
+    let mut succeeded = Vec::new();
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok((termination, program_stdout, program_stderr)) => {
+                writeln!(stdout, "==> {}: {}", outcome.toolchain, termination)?;
+                stdout.write_all(program_stdout)?;
+                if !program_stderr.is_empty() {
+                    writeln!(stdout, "--- stderr")?;
+                    stdout.write_all(program_stderr)?;
+                }
+                succeeded.push((&outcome.toolchain, termination.exit_code(), program_stdout));
+            }
+            Err(e) => writeln!(stdout, "==> {}: {}", outcome.toolchain, e)?,
+        }
+    }
+
+    // 最初にビルドできたツールチェインの結果を基準にして比べる
+    writeln!(stdout)?;
+    let (base_toolchain, base_code, base_output) = match succeeded.first() {
+        Some(base) => *base,
+        None => {
+            writeln!(stdout, "no toolchain could run the program.")?;
+            return Ok(1);
+        }
+    };
+    let base_stdout = String::from_utf8_lossy(base_output);
+    let mut all_same = true;
+    for (toolchain, code, output) in &succeeded[1..] {
+        let output_stdout = String::from_utf8_lossy(output);
+        if *code == base_code && output_stdout == base_stdout {
+            continue;
+        }
+
+        all_same = false;
+        writeln!(stdout, "==> {} differs from {}", toolchain, base_toolchain)?;
+        if *code != base_code {
+            writeln!(stdout, "exit code: {} -> {}", base_code, code)?;
+        }
+        if output_stdout != base_stdout {
+            let diff = TextDiff::from_lines(&*base_stdout, &*output_stdout);
+            write!(
+                stdout,
+                "{}",
+                diff.unified_diff().header(base_toolchain, toolchain)
+            )?;
+        }
+    }
+
+    let all_built = succeeded.len() == outcomes.len();
+    if all_same {
+        writeln!(
+            stdout,
+            "all toolchains that ran the program produced the same output."
+        )?;
+    }
+    if !all_built {
+        let failed: Vec<&str> = outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .map(|outcome| outcome.toolchain.as_str())
+            .collect();
+        writeln!(stdout, "failed toolchains: {}", failed.join(", "))?;
+    }
+    stdout.flush()?;
+
+    Ok(if all_built && all_same { 0 } else { 1 })
+}
+
+/// 一つのツールチェインでビルドして実行する。
+fn run_toolchain(
+    toolchain: &str,
+    content: &str,
+    input: &[u8],
+    args: &Args,
+    config: &Config,
+) -> Fallible<(Termination, Vec<u8>, Vec<u8>)> {
+    let mut options = args.options.clone();
+    options.push((OptionType::Toolchain, toolchain.to_string()));
+    let context = Context::parse(content, &args.source_file.base_dir()?, config, &options)?;
+
+    let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+    let binary = crate::prepare_binary(tmpdir.path(), content, &context, args.no_cache)?;
+    let (outcome, stdout, stderr) =
+        crate::run_with_input(&binary, &args.program_args, input, &context.limits)?;
+
+    Ok((outcome.termination, stdout, stderr))
+}
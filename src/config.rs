@@ -0,0 +1,116 @@
+//! rust-runner の設定ファイル。
+//!
+//! `$XDG_CONFIG_HOME/rust-runner/config.toml` から読み込む。ファイルがなければすべて既定値になる。
+//!
+//! ```toml
+//! # ネットワークを使わず、ローカルに保存したクレートだけでビルドする
+//! offline = true
+//! # ローカルに保存したクレートのディレクトリ (省略すると $XDG_DATA_HOME/rust-runner/vendor)
+//! vendor-dir = "/path/to/vendor"
+//! # インストールされていないツールチェインが指定されたら rustup でインストールする
+//! install-toolchain = true
+//!
+//! # インポート名からパッケージへの対応表
+//! [crates.proc_macro2]
+//! package = "proc-macro2"
+//! version = "1"
+//! features = ["span-locations"]
+//! default-features = false
+//!
+//! # rust-runner bundle で提出先のジャッジが使えるクレート
+//! [bundle]
+//! allowed-crates = ["proconio", "itertools"]
+//!
+//! # rust-runner bundle で展開するライブラリクレートのインポート名とディレクトリ
+//! [bundle.libraries]
+//! our_lib = "/path/to/our_lib"
+//! ```
+
+use failure::{bail, Fallible};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BUILTIN_CRATES: &str = include_str!("builtin_crates.toml");
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// オフラインモードを使うか。
+    #[serde(default)]
+    pub offline: bool,
+    /// オフラインモードで使うクレートのディレクトリ。
+    pub vendor_dir: Option<PathBuf>,
+    /// 指定されたツールチェインがインストールされていなければ rustup でインストールするか。
+    #[serde(default)]
+    pub install_toolchain: bool,
+    /// インポート名 (`use` で書く名前) からパッケージの指定への対応表。
+    #[serde(default)]
+    pub crates: BTreeMap<String, CrateMapping>,
+    /// `rust-runner bundle` の設定。
+    #[serde(default)]
+    pub bundle: BundleConfig,
+    /// スクリプトでもコマンドラインでもプロファイルが指定されていないときに使うプロファイル。設定ファイルでは
+    /// 指定できず、サブコマンドが決める。`None` なら dev にする。
+    #[serde(skip)]
+    pub default_profile: Option<String>,
+}
+
+/// インポート名に対応するパッケージの指定。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateMapping {
+    /// パッケージ名。省略するとインポート名と同じになる。
+    pub package: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "default_true")]
+    pub default_features: bool,
+}
+
+/// `rust-runner bundle` の設定。
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BundleConfig {
+    /// 展開するライブラリクレートのインポート名から、そのクレートのディレクトリ (または `lib.rs`) への対応表。
+    #[serde(default)]
+    pub libraries: BTreeMap<String, PathBuf>,
+    /// 提出先のジャッジで使えるクレートのインポート名。
+    #[serde(default)]
+    pub allowed_crates: Vec<String>,
+}
+
+impl Config {
+    /// 設定ファイルを読み込み、組み込みの対応表とあわせた設定を返す。
+    pub fn load() -> Fallible<Config> {
+        let mut config = match dirs::config_dir() {
+            Some(dir) => Config::load_file(&dir.join("rust-runner").join("config.toml"))?,
+            None => Config::default(),
+        };
+
+        // ユーザーが指定していないものには組み込みの対応表を使う
+        let builtin: Config = toml::from_str(BUILTIN_CRATES)?;
+        for (name, mapping) in builtin.crates {
+            config.crates.entry(name).or_insert(mapping);
+        }
+
+        Ok(config)
+    }
+
+    fn load_file(path: &Path) -> Fallible<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        match toml::from_str(&fs::read_to_string(path)?) {
+            Ok(config) => Ok(config),
+            Err(e) => bail!("failed to load {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
@@ -0,0 +1,141 @@
+//! プロジェクトに追加する依存クレートの指定。
+
+use crate::config::CrateMapping;
+use failure::{bail, Fallible};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref RE_DEPENDENCY: Regex = Regex::new(
+        r#"^(?P<name>[\w-]+)(@(?P<version>[^\[\]\s]+))?(\[(?P<features>[^\[\]]*)\])?$"#
+    )
+    .unwrap();
+}
+
+/// 依存クレート一つ分の指定。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// ソースコードから参照する名前。
+    pub name: String,
+    /// `name` と異なる名前のパッケージを使う場合のパッケージ名。
+    pub package: Option<String>,
+    pub version: Option<String>,
+    pub features: Vec<String>,
+    pub default_features: bool,
+}
+
+impl Dependency {
+    /// バージョンや features を指定しない依存クレートを作る。
+    pub fn new(name: &str) -> Dependency {
+        Dependency {
+            name: name.to_string(),
+            package: None,
+            version: None,
+            features: Vec::new(),
+            default_features: true,
+        }
+    }
+
+    /// 設定の対応表にしたがって依存クレートを作る。
+    pub fn from_mapping(name: &str, mapping: &CrateMapping) -> Dependency {
+        Dependency {
+            name: name.to_string(),
+            package: mapping.package.clone(),
+            version: mapping.version.clone(),
+            features: mapping.features.clone(),
+            default_features: mapping.default_features,
+        }
+    }
+
+    /// `name@version[feature1, feature2, default-features=false]` の形の指定をパースする。
+    ///
+    /// バージョンと features の指定はどちらも省略できる。`num-bigint` のように `-` を含む名前は、
+    /// インポートから検出したものと同じになるよう `-` を `_` にした名前で参照し、書かれた名前を
+    /// パッケージ名にする。
+    pub fn parse(spec: &str) -> Fallible<Dependency> {
+        let captures = match RE_DEPENDENCY.captures(spec.trim()) {
+            Some(captures) => captures,
+            None => bail!("invalid dependency: {}", spec),
+        };
+
+        let package = &captures["name"];
+        let mut dependency = Dependency::new(&package.replace('-', "_"));
+        if dependency.name != package {
+            dependency.package = Some(package.to_string());
+        }
+        dependency.version = captures.name("version").map(|m| m.as_str().to_string());
+        let features = captures.name("features").map_or("", |m| m.as_str());
+        for feature in features.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            match feature {
+                "default-features=false" => dependency.default_features = false,
+                "default-features=true" => dependency.default_features = true,
+                _ => dependency.features.push(feature.to_string()),
+            }
+        }
+
+        Ok(dependency)
+    }
+
+    /// この依存クレートを追加する `cargo add` の引数を返す。
+    pub fn cargo_add_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let package = self.package.as_ref().unwrap_or(&self.name);
+        match &self.version {
+            Some(version) => args.push(format!("{}@{}", package, version)),
+            None => args.push(package.clone()),
+        }
+
+        // ライブラリ名がパッケージ名の `-` を `_` にしたものと異なる場合に備え、参照する名前で依存を登録する
+        if package.replace('-', "_") != self.name {
+            args.push("--rename".to_string());
+            args.push(self.name.clone());
+        }
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(","));
+        }
+        if !self.default_features {
+            args.push("--no-default-features".to_string());
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dependency;
+
+    #[test]
+    fn parse_name_only() {
+        let dependency = Dependency::parse("rand").unwrap();
+        assert_eq!(dependency, Dependency::new("rand"));
+        assert_eq!(dependency.cargo_add_args(), ["rand"]);
+    }
+
+    #[test]
+    fn parse_version_and_features() {
+        let dependency = Dependency::parse("serde@1.0[derive, default-features=false]").unwrap();
+        assert_eq!(dependency.version.as_deref(), Some("1.0"));
+        assert_eq!(dependency.features, ["derive"]);
+        assert!(!dependency.default_features);
+        assert_eq!(
+            dependency.cargo_add_args(),
+            ["serde@1.0", "--features", "derive", "--no-default-features"]
+        );
+    }
+
+    #[test]
+    fn parse_hyphenated_name() {
+        let dependency = Dependency::parse("num-bigint@0.4").unwrap();
+        assert_eq!(dependency.name, "num_bigint");
+        assert_eq!(dependency.package.as_deref(), Some("num-bigint"));
+        assert_eq!(dependency.cargo_add_args(), ["num-bigint@0.4"]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Dependency::parse("rand@").is_err());
+        assert!(Dependency::parse("rand[derive").is_err());
+    }
+}
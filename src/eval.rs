@@ -0,0 +1,79 @@
+//! `-e` で指定されたコード片を `fn main` に包んだプログラムにする。
+
+use syn::parse::Parser;
+use syn::spanned::Spanned;
+use syn::{Block, Stmt};
+
+/// 値を `{:?}` で表示するヘルパー。値がユニット型の場合は何も表示しない。
+///
+/// ユニット型かどうかは自動参照によるメソッド解決の優先順位で振り分ける。値はムーブせず参照で受け取る。
+const PRINTER: &str = r#"
+    struct __RustRunnerValue<T>(T);
+    trait __RustRunnerPrintUnit { fn __rust_runner_print(&self); }
+    impl __RustRunnerPrintUnit for __RustRunnerValue<&()> { fn __rust_runner_print(&self) {} }
+    trait __RustRunnerPrintDebug { fn __rust_runner_print(&self); }
+    impl<T: ::std::fmt::Debug + ?Sized> __RustRunnerPrintDebug for &__RustRunnerValue<&T> {
+        fn __rust_runner_print(&self) { println!("{:?}", self.0); }
+    }
+"#;
+
+/// コード片を `fn main` に包む。
+///
+/// 先頭のコメント行は `rust-runner:` オプションとして読めるよう `fn main` の外に置く。コード片が末尾に
+/// セミコロンのない式を持つ場合は、その値を `{:?}` で表示する。
+pub fn wrap_snippet(snippet: &str) -> String {
+    let header_len = snippet
+        .lines()
+        .take_while(|line| line.trim().is_empty() || line.trim_start().starts_with("//"))
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        .min(snippet.len());
+    let (header, body) = snippet.split_at(header_len);
+
+    let (statements, tail) = split_tail(body);
+    let mut program = format!("{}\nfn main() {{\n{}\n", header, statements);
+    if let Some(tail) = tail {
+        program.push_str(&print_value(tail));
+    }
+    program.push_str("}\n");
+
+    program
+}
+
+/// 式の値を表示する文を作る。
+pub fn print_value(expr: &str) -> String {
+    format!(
+        "    {{\n{}\n    (&__RustRunnerValue(&(\n{}\n    ))).__rust_runner_print();\n    }}\n",
+        PRINTER, expr
+    )
+}
+
+/// 文の並びを、末尾のセミコロンのない式とそれより前の部分に分ける。
+///
+/// 末尾が式でない場合や、構文解析できない場合は全体を前の部分とする。
+pub fn split_tail(body: &str) -> (&str, Option<&str>) {
+    let stmts = match Block::parse_within.parse_str(body) {
+        Ok(stmts) => stmts,
+        Err(_) => return (body, None),
+    };
+
+    let tail_start = match stmts.last() {
+        Some(Stmt::Expr(expr, None)) => expr.span().start(),
+        Some(Stmt::Macro(mac)) if mac.semi_token.is_none() => mac.span().start(),
+        _ => return (body, None),
+    };
+
+    // 行と列 (文字単位) からバイト位置を求める
+    let offset: usize = body
+        .split_inclusive('\n')
+        .take(tail_start.line - 1)
+        .map(str::len)
+        .sum();
+    let offset = offset
+        + body[offset..]
+            .char_indices()
+            .nth(tail_start.column)
+            .map_or(body.len() - offset, |(i, _)| i);
+
+    (&body[..offset], Some(&body[offset..]))
+}
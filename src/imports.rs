@@ -0,0 +1,439 @@
+//! ソースコードが参照している外部クレートの検出。
+//!
+//! ソースコードを構文解析し、2 セグメント以上のパス (`rand::random()` や `serde::Serialize` など) の
+//! 先頭のセグメント、`use` ツリーの根 (`use {rand::Rng, regex::Regex};` なら `rand` と `regex`)、
+//! `extern crate` の名前を集める。関数やモジュールの中にある `use` も対象になる。マクロの引数や属性の中は
+//! 構文解析できないので、トークン列から `ident::` の形を探す。
+//!
+//! ローカルに定義されたモジュールやアイテム、ジェネリクスの型引数、`use` で導入した名前、prelude や
+//! プリミティブ型の名前は外部クレートではないので取り除く。ただし `use lazy_static::lazy_static;` の
+//! ように `use` で導入した名前と同じ名前のクレートもあるので、`use` ツリーの根はローカルに定義された
+//! 名前でだけ取り除く。`::rand::Rng` のように `::` で始まるパスと `extern crate` は必ず外部クレートを
+//! 指すので、ローカルな名前と同じでも取り除かない。
+//!
+//! グロブインポートで導入される名前は分からない。`use std::collections::*;` のようにクレートでないものを
+//! グロブインポートしている場合は、`HashMap::new()` の `HashMap` なども外部クレートに見えてしまうので、
+//! `use` ツリーの根以外のパスからはクレートを探さない。クレートをグロブインポートしている場合も、
+//! 大文字で始まるパスの先頭は型などとみなして取り除く。
+
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use std::collections::BTreeSet;
+use syn::visit::{self, Visit};
+use syn::{Ident, Meta, UseTree};
+
+/// クレートではない、言語や標準ライブラリが定義している名前。
+const BUILTIN_NAMES: &[&str] = &[
+    // 標準のクレートとパスのキーワード
+    "std", "core", "alloc", "proc_macro", "test", "crate", "self", "super", "Self",
+    // プリミティブ型
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+    // prelude
+    "Option", "Some", "None", "Result", "Ok", "Err", "Vec", "String", "Box", "ToString",
+    "ToOwned", "Iterator", "IntoIterator", "DoubleEndedIterator", "ExactSizeIterator", "Extend",
+    "FromIterator", "Default", "Clone", "Copy", "Send", "Sync", "Sized", "Unpin", "Drop", "Fn",
+    "FnMut", "FnOnce", "PartialEq", "Eq", "PartialOrd", "Ord", "AsRef", "AsMut", "Into", "From",
+    "TryFrom", "TryInto",
+    // ツール属性の名前空間
+    "clippy", "rustfmt", "rustdoc", "diagnostic",
+];
+
+/// ソースコードが参照している外部クレートの名前を集める。
+pub fn find_external_crates(content: &str) -> BTreeSet<String> {
+    let mut collector = Collector::default();
+    match syn::parse_file(content) {
+        Ok(file) => collector.visit_file(&file),
+        Err(_) => {
+            // 構文解析できない場合はトークン列から推測する
+            if let Ok(tokens) = content.parse::<TokenStream>() {
+                collector.scan_keywords(tokens.clone());
+                collector.scan_tokens(tokens);
+            }
+        }
+    }
+
+    collector.into_crates()
+}
+
+#[derive(Default)]
+struct Collector {
+    /// パスの先頭のセグメント。ローカルな名前でも `use` で導入された名前でもないものが外部クレートになる。
+    path_roots: BTreeSet<String>,
+    /// `use` ツリーの根。ローカルな名前でないものが外部クレートになる。
+    use_roots: BTreeSet<String>,
+    /// `::` で始まるパスや `extern crate` で指定された、必ず外部クレートを指す名前。
+    absolute_roots: BTreeSet<String>,
+    /// ローカルに定義された名前。
+    locals: BTreeSet<String>,
+    /// `use` で導入された名前。
+    use_names: BTreeSet<String>,
+    /// グロブインポートしている `use` ツリーの根。
+    glob_roots: BTreeSet<String>,
+}
+
+impl Collector {
+    fn into_crates(self) -> BTreeSet<String> {
+        let locals = self.locals;
+        let use_names = self.use_names;
+        let is_crate = |name: &str| !locals.contains(name) && !BUILTIN_NAMES.contains(&name);
+        let non_crate_glob = self.glob_roots.iter().any(|root| !is_crate(root));
+        let crate_glob = !self.glob_roots.is_empty();
+        let path_roots = self
+            .path_roots
+            .into_iter()
+            .filter(|_| !non_crate_glob)
+            .filter(|name| !crate_glob || !name.starts_with(char::is_uppercase))
+            .filter(|name| !locals.contains(name) && !use_names.contains(name));
+        let use_roots = self
+            .use_roots
+            .into_iter()
+            .filter(|name| !locals.contains(name));
+
+        self.absolute_roots
+            .into_iter()
+            .chain(path_roots)
+            .chain(use_roots)
+            .filter(|name| !BUILTIN_NAMES.contains(&name.as_str()))
+            .collect()
+    }
+
+    fn add_local(&mut self, ident: &Ident) {
+        self.locals.insert(unraw(ident));
+    }
+
+    fn add_use_name(&mut self, ident: &Ident) {
+        self.use_names.insert(unraw(ident));
+    }
+
+    /// `use` ツリーの根を集める。グループの場合はその要素それぞれの根を集める。
+    fn add_use_roots(&mut self, tree: &UseTree, absolute: bool) {
+        let root = match tree {
+            UseTree::Path(path) => &path.ident,
+            UseTree::Name(name) => &name.ident,
+            UseTree::Rename(rename) => &rename.ident,
+            UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.add_use_roots(tree, absolute);
+                }
+                return;
+            }
+            UseTree::Glob(_) => return,
+        };
+
+        if has_glob(tree) {
+            self.glob_roots.insert(unraw(root));
+        }
+        if absolute {
+            self.absolute_roots.insert(unraw(root));
+        } else {
+            self.use_roots.insert(unraw(root));
+        }
+    }
+
+    /// `use` で導入される名前を集める。
+    ///
+    /// `use rand;` のように根をそのまま導入する場合、その名前は外部クレートそのものなのでローカルな名前に
+    /// しない。
+    fn add_use_names(&mut self, tree: &UseTree, parent: Option<&Ident>) {
+        match tree {
+            UseTree::Path(path) => self.add_use_names(&path.tree, Some(&path.ident)),
+            UseTree::Name(name) if name.ident == "self" => {
+                if let Some(parent) = parent {
+                    self.add_use_name(parent);
+                }
+            }
+            UseTree::Name(name) => {
+                if parent.is_some() {
+                    self.add_use_name(&name.ident);
+                }
+            }
+            UseTree::Rename(rename) => self.add_use_name(&rename.rename),
+            UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.add_use_names(tree, parent);
+                }
+            }
+            UseTree::Glob(_) => {}
+        }
+    }
+
+    /// マクロの引数や属性の中のトークン列から、パスの先頭のセグメントらしきものを探す。
+    ///
+    /// `ident::` の形で、直前が `::` や `.` (メソッドのターボフィッシュ)、`$` (マクロの変数) でないものを集める。
+    fn scan_tokens(&mut self, tokens: TokenStream) {
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                TokenTree::Group(group) => self.scan_tokens(group.stream()),
+                TokenTree::Ident(ident) => {
+                    let prev = i.checked_sub(1).and_then(|i| tokens.get(i));
+                    let preceded_by_separator = match prev {
+                        Some(TokenTree::Punct(punct)) => matches!(punct.as_char(), ':' | '.' | '$'),
+                        _ => false,
+                    };
+                    if !preceded_by_separator && is_path_separator(&tokens[i + 1..]) {
+                        self.path_roots.insert(unraw(ident));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 構文解析できなかったソースコードのトークン列から、`use` や `extern crate` の直後の名前をクレート名、
+    /// `mod` や `fn` などの直後の名前をローカルな名前として集める。
+    fn scan_keywords(&mut self, tokens: TokenStream) {
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        for (i, token) in tokens.iter().enumerate() {
+            match (token, tokens.get(i + 1)) {
+                (TokenTree::Group(group), _) => self.scan_keywords(group.stream()),
+                (TokenTree::Ident(keyword), Some(next)) => match keyword.to_string().as_str() {
+                    "use" => self.scan_use_roots(&tokens[i + 1..]),
+                    "crate" => {
+                        if let TokenTree::Ident(ident) = next {
+                            self.absolute_roots.insert(unraw(ident));
+                        }
+                    }
+                    "mod" | "fn" | "struct" | "enum" | "union" | "trait" | "type" | "const"
+                    | "static" => {
+                        if let TokenTree::Ident(ident) = next {
+                            self.add_local(ident);
+                        }
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// `use` の直後のトークン列から `use` ツリーの根を集める。
+    fn scan_use_roots(&mut self, tokens: &[TokenTree]) {
+        match tokens {
+            [TokenTree::Punct(_), TokenTree::Punct(_), TokenTree::Ident(ident), ..]
+                if is_path_separator(tokens) =>
+            {
+                self.absolute_roots.insert(unraw(ident));
+            }
+            [TokenTree::Ident(ident), ..] => {
+                self.use_roots.insert(unraw(ident));
+            }
+            [TokenTree::Group(group), ..] if group.delimiter() == Delimiter::Brace => {
+                // グループの各要素の先頭を見る
+                let items: Vec<TokenTree> = group.stream().into_iter().collect();
+                for item in items.split(|token| match token {
+                    TokenTree::Punct(punct) => punct.as_char() == ',',
+                    _ => false,
+                }) {
+                    self.scan_use_roots(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Collector {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        // 1 セグメントのパスはローカル変数などでもありうるので、2 セグメント以上のものだけを見る
+        if path.leading_colon.is_some() {
+            self.absolute_roots.insert(unraw(&path.segments[0].ident));
+        } else if path.segments.len() >= 2 {
+            self.path_roots.insert(unraw(&path.segments[0].ident));
+        }
+
+        visit::visit_path(self, path);
+    }
+
+    fn visit_item_use(&mut self, item: &'ast syn::ItemUse) {
+        self.add_use_roots(&item.tree, item.leading_colon.is_some());
+        self.add_use_names(&item.tree, None);
+
+        visit::visit_item_use(self, item);
+    }
+
+    fn visit_item_extern_crate(&mut self, item: &'ast syn::ItemExternCrate) {
+        self.absolute_roots.insert(unraw(&item.ident));
+        if let Some((_, rename)) = &item.rename {
+            self.add_local(rename);
+        }
+
+        visit::visit_item_extern_crate(self, item);
+    }
+
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        self.scan_tokens(mac.tokens.clone());
+
+        visit::visit_macro(self, mac);
+    }
+
+    fn visit_meta(&mut self, meta: &'ast Meta) {
+        // `#[derive(serde::Serialize)]` などの属性の中身はトークン列のままなので探す
+        if let Meta::List(list) = meta {
+            self.scan_tokens(list.tokens.clone());
+        }
+
+        visit::visit_meta(self, meta);
+    }
+
+    fn visit_item_mod(&mut self, item: &'ast syn::ItemMod) {
+        self.add_local(&item.ident);
+        visit::visit_item_mod(self, item);
+    }
+
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        self.add_local(&item.sig.ident);
+        visit::visit_item_fn(self, item);
+    }
+
+    fn visit_item_struct(&mut self, item: &'ast syn::ItemStruct) {
+        self.add_local(&item.ident);
+        visit::visit_item_struct(self, item);
+    }
+
+    fn visit_item_enum(&mut self, item: &'ast syn::ItemEnum) {
+        self.add_local(&item.ident);
+        visit::visit_item_enum(self, item);
+    }
+
+    fn visit_item_union(&mut self, item: &'ast syn::ItemUnion) {
+        self.add_local(&item.ident);
+        visit::visit_item_union(self, item);
+    }
+
+    fn visit_item_trait(&mut self, item: &'ast syn::ItemTrait) {
+        self.add_local(&item.ident);
+        visit::visit_item_trait(self, item);
+    }
+
+    fn visit_item_type(&mut self, item: &'ast syn::ItemType) {
+        self.add_local(&item.ident);
+        visit::visit_item_type(self, item);
+    }
+
+    fn visit_item_const(&mut self, item: &'ast syn::ItemConst) {
+        self.add_local(&item.ident);
+        visit::visit_item_const(self, item);
+    }
+
+    fn visit_item_static(&mut self, item: &'ast syn::ItemStatic) {
+        self.add_local(&item.ident);
+        visit::visit_item_static(self, item);
+    }
+
+    fn visit_item_macro(&mut self, item: &'ast syn::ItemMacro) {
+        // `macro_rules!` で定義したマクロは `use` で再エクスポートされることがある
+        if let Some(ident) = &item.ident {
+            self.add_local(ident);
+        }
+        visit::visit_item_macro(self, item);
+    }
+
+    fn visit_type_param(&mut self, param: &'ast syn::TypeParam) {
+        self.add_local(&param.ident);
+        visit::visit_type_param(self, param);
+    }
+
+    fn visit_const_param(&mut self, param: &'ast syn::ConstParam) {
+        self.add_local(&param.ident);
+        visit::visit_const_param(self, param);
+    }
+}
+
+/// `use` ツリーがグロブインポートを含むか。
+fn has_glob(tree: &UseTree) -> bool {
+    match tree {
+        UseTree::Path(path) => has_glob(&path.tree),
+        UseTree::Glob(_) => true,
+        UseTree::Group(group) => group.items.iter().any(has_glob),
+        UseTree::Name(_) | UseTree::Rename(_) => false,
+    }
+}
+
+/// トークン列が `::` で始まっているか。
+fn is_path_separator(tokens: &[TokenTree]) -> bool {
+    match tokens {
+        [TokenTree::Punct(first), TokenTree::Punct(second), ..] => {
+            first.as_char() == ':' && first.spacing() == Spacing::Joint && second.as_char() == ':'
+        }
+        _ => false,
+    }
+}
+
+/// `r#` 付きの識別子から `r#` を取り除いた名前を得る。
+fn unraw(ident: &Ident) -> String {
+    let ident = ident.to_string();
+    match ident.strip_prefix("r#") {
+        Some(ident) => ident.to_string(),
+        None => ident,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_external_crates;
+
+    fn crates(content: &str) -> Vec<String> {
+        find_external_crates(content).into_iter().collect()
+    }
+
+    #[test]
+    fn use_of_same_name_as_crate() {
+        assert_eq!(crates("use foo::foo;"), ["foo"]);
+        assert_eq!(crates("use lazy_static::lazy_static;"), ["lazy_static"]);
+    }
+
+    #[test]
+    fn grouped_use() {
+        assert_eq!(crates("use anyhow::{anyhow, Result};"), ["anyhow"]);
+        assert_eq!(crates("use {rand::Rng, regex::Regex};"), ["rand", "regex"]);
+    }
+
+    #[test]
+    fn absolute_path() {
+        assert_eq!(crates("use ::rand::Rng;"), ["rand"]);
+        assert_eq!(
+            crates("mod rand {} fn f() { ::rand::random::<u32>(); }"),
+            ["rand"]
+        );
+    }
+
+    #[test]
+    fn local_module() {
+        assert!(crates("mod m { pub fn x() {} } use m::x;").is_empty());
+        assert!(crates("mod m { pub mod n {} } use m::{n, self};").is_empty());
+    }
+
+    #[test]
+    fn glob_import() {
+        assert!(crates(
+            "use std::collections::*; fn f() { HashMap::<u32, u32>::new(); VecDeque::<u32>::new(); }"
+        )
+        .is_empty());
+        assert_eq!(
+            crates("use std::io::*; use rand::Rng; fn f() { stdin(); BufReader::new(stdin()); }"),
+            ["rand"]
+        );
+        assert_eq!(
+            crates(
+                "use proconio::marker::*; fn f() { Usize1::default(); itertools::repeat_n(0, 1); }"
+            ),
+            ["itertools", "proconio"]
+        );
+    }
+
+    #[test]
+    fn tool_attributes() {
+        assert!(crates("#[allow(clippy::needless_range_loop)] fn f() {}").is_empty());
+        assert!(crates("#[rustfmt::skip] fn f() {}").is_empty());
+    }
+
+    #[test]
+    fn name_introduced_by_use() {
+        assert_eq!(crates("use rand::Rng; fn f() { Rng::gen(); }"), ["rand"]);
+        assert_eq!(
+            crates("use rand as r; fn f() { r::random::<u32>(); }"),
+            ["rand"]
+        );
+    }
+}
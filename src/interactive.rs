@@ -0,0 +1,129 @@
+//! `--interactor` による対話ジャッジとの接続。
+//!
+//! 対話ジャッジも通常の実行と同じようにビルドし、プログラムの標準出力を対話ジャッジの標準入力に、
+//! 対話ジャッジの標準出力をプログラムの標準入力につないで両方を実行する。やりとりは行ごとに向きの印を
+//! 付けて標準エラー出力に表示する。`> ` はプログラムから対話ジャッジへ、`< ` は対話ジャッジから
+//! プログラムへの出力を表す。
+//!
+//! 対話ジャッジの終了コードを判定結果とみなし、rust-runner の終了コードとして返す。ただし、プログラムが
+//! 時間やメモリの制限を超えた場合はそれを表す終了コードを返す。
+
+use crate::config::Config;
+use crate::limit::{self, Termination};
+use crate::{Args, Context, SourceFile};
+use failure::Fallible;
+use std::io::prelude::*;
+use std::io::{stderr, Stderr};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use tempfile::Builder;
+
+/// 対話ジャッジをビルドし、ビルド済みのプログラムとつないで実行する。
+pub fn run(
+    binary: &Path,
+    interactor: &Path,
+    args: &Args,
+    context: &Context,
+    config: &Config,
+) -> Fallible<i32> {
+    // 対話ジャッジにはコマンドラインのオプションを適用せず、自身のソースコード中の指定だけを使う
+    let source_file = SourceFile::Path(interactor.to_path_buf());
+    let content = source_file.read_content()?;
+    let interactor_context = Context::parse(&content, &source_file.base_dir()?, config, &[])?;
+    let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+    let interactor_binary =
+        crate::prepare_binary(tmpdir.path(), &content, &interactor_context, args.no_cache)?;
+
+    let mut interactor = Command::new(interactor_binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut command = Command::new(binary);
+    command
+        .args(&args.program_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    limit::apply(&mut command, &context.limits);
+    let mut program = match command.spawn() {
+        Ok(program) => program,
+        Err(e) => {
+            let _ = interactor.kill();
+            interactor.wait()?;
+            return Err(e.into());
+        }
+    };
+
+    let transcript = Mutex::new(stderr());
+    let (outcome, interactor_status) = thread::scope(|scope| -> Fallible<_> {
+        let program_stdin = program.stdin.take().unwrap();
+        let program_stdout = program.stdout.take().unwrap();
+        let interactor_stdin = interactor.stdin.take().unwrap();
+        let interactor_stdout = interactor.stdout.take().unwrap();
+        let transcript = &transcript;
+        scope.spawn(move || relay(program_stdout, interactor_stdin, "> ", transcript));
+        scope.spawn(move || relay(interactor_stdout, program_stdin, "< ", transcript));
+
+        // どちらかが終了すると出力のパイプが閉じ、もう一方の入力も閉じられる
+        let outcome = limit::wait(&mut program, &context.limits)?;
+        let interactor_status = interactor.wait()?;
+
+        Ok((outcome, interactor_status))
+    })?;
+
+    let interactor_code = crate::exit_code_of(interactor_status);
+    eprintln!("program: {}", outcome.termination);
+    eprintln!("interactor: exited with code {}", interactor_code);
+
+    Ok(match outcome.termination {
+        Termination::TimeLimitExceeded | Termination::MemoryLimitExceeded => {
+            outcome.termination.exit_code()
+        }
+        Termination::Exited(_) => interactor_code,
+    })
+}
+
+/// `from` から読んだ内容を `to` へ書き込み、行ごとに向きの印を付けてやりとりの記録に表示する。
+fn relay(mut from: impl Read, to: impl Write, marker: &str, transcript: &Mutex<Stderr>) {
+    let mut to = Some(to);
+    let mut buf = [0; 8192];
+    let mut line = Vec::new();
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+
+        // 受け手の返事より先に表示されるよう、渡す前に記録する
+        line.extend_from_slice(&buf[..n]);
+        while let Some(pos) = line.iter().position(|&b| b == b'\n') {
+            let rest = line.split_off(pos + 1);
+            write_transcript(transcript, marker, &line);
+            line = rest;
+        }
+
+        // 受け手が終了して書き込めなくなっても、送り手が詰まらないよう読み続ける
+        if let Some(writer) = &mut to {
+            if writer
+                .write_all(&buf[..n])
+                .and_then(|_| writer.flush())
+                .is_err()
+            {
+                to = None;
+            }
+        }
+    }
+
+    // 改行で終わっていない最後の出力も表示する
+    if !line.is_empty() {
+        line.push(b'\n');
+        write_transcript(transcript, marker, &line);
+    }
+}
+
+fn write_transcript(transcript: &Mutex<Stderr>, marker: &str, line: &[u8]) {
+    let mut transcript = transcript.lock().unwrap();
+    let _ = transcript.write_all(marker.as_bytes());
+    let _ = transcript.write_all(line);
+}
@@ -0,0 +1,268 @@
+//! `rust-runner judge` による競技プログラミングのサンプルケースの判定。
+//!
+//! プロファイルの指定がなければ release で一度だけビルドし、テストケースのディレクトリにある `*.in` をそれぞれ標準入力に
+//! 与えて実行する。標準出力を同じ名前の `*.out` と比べ、ケースごとに AC (正解)、WA (不正解)、RE (実行時
+//! エラー)、TLE (時間超過)、MLE (メモリ超過) のいずれかを表示する。AC でないケースには期待する出力との差分を表示する。
+
+use crate::config::Config;
+use crate::limit::Termination;
+use crate::{Args, Context};
+use failure::{bail, Fallible};
+use similar::TextDiff;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::Builder;
+
+const USAGE: &str = "usage: rust-runner judge [--compare exact|whitespace|float[=<eps>]] [--time-limit <time>] [<flags>...] <source> <tests dir> [<args>...]";
+
+/// 浮動小数点数の比較で既定で許す誤差。
+const DEFAULT_EPS: f64 = 1e-6;
+
+/// 時間制限が指定されていない場合の時間制限。
+const DEFAULT_TIME_LIMIT: Duration = Duration::from_secs(2);
+
+/// 出力の比べ方。
+#[derive(Debug, Clone, Copy)]
+pub enum Comparator {
+    /// バイト列として完全に一致するか。
+    Exact,
+    /// 空白で区切った単語の列が一致するか。
+    Whitespace,
+    /// 空白で区切った単語の列が一致するか。ただし両方が数として読める単語は、絶対誤差または相対誤差が
+    /// 指定した値以下なら一致とみなす。
+    Float(f64),
+}
+
+/// 一つのケースの判定結果。
+enum Verdict {
+    Accepted,
+    WrongAnswer,
+    RuntimeError(i32),
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+}
+
+/// `rust-runner judge` を実行する。
+pub fn command(args: &[&str], mut config: Config) -> Fallible<i32> {
+    let mut comparator = Comparator::Whitespace;
+    let mut rest = args;
+    while let ["--compare", mode, tail @ ..] = rest {
+        comparator = Comparator::parse(mode)?;
+        rest = tail;
+    }
+
+    // 残りは通常の実行と同じようにパースする。ソースファイルの次の引数がテストケースのディレクトリになる。
+    let mut args = Args::parse_timing_subcommand_args("judge", rest, &mut config)?;
+    if let crate::SourceFile::Stdin = args.source_file {
+        bail!("a source file is required.\n{}", USAGE);
+    }
+    if args.program_args.is_empty() {
+        bail!("a test case directory is required.\n{}", USAGE);
+    }
+    let tests_dir = PathBuf::from(args.program_args.remove(0));
+    let cases = find_cases(&tests_dir)?;
+    if cases.is_empty() {
+        bail!("no test cases (*.in) found in {}", tests_dir.display());
+    }
+
+    let content = args.source_file.read_content()?;
+    let mut context = Context::parse(
+        &content,
+        &args.source_file.base_dir()?,
+        &config,
+        &args.options,
+    )?;
+    context.limits.time.get_or_insert(DEFAULT_TIME_LIMIT);
+    let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+    let binary = crate::prepare_binary(tmpdir.path(), &content, &context, args.no_cache)?;
+
+    let mut accepted = 0;
+    for (name, input, answer) in &cases {
+        let input = fs::read(input)?;
+        let answer = match answer {
+            Some(answer) => fs::read(answer)?,
+            None => {
+                println!("{}: skipped (no .out file)", name);
+                continue;
+            }
+        };
+
+        let (outcome, stdout, stderr) =
+            crate::run_with_input(&binary, &args.program_args, &input, &context.limits)?;
+        let verdict = match outcome.termination {
+            Termination::TimeLimitExceeded => Verdict::TimeLimitExceeded,
+            Termination::MemoryLimitExceeded => Verdict::MemoryLimitExceeded,
+            Termination::Exited(status) if !status.success() => {
+                Verdict::RuntimeError(crate::exit_code_of(status))
+            }
+            Termination::Exited(_) if comparator.matches(&stdout, &answer) => Verdict::Accepted,
+            Termination::Exited(_) => Verdict::WrongAnswer,
+        };
+        println!("{}: {} ({} ms)", name, verdict, outcome.elapsed.as_millis());
+
+        match verdict {
+            Verdict::Accepted => accepted += 1,
+            Verdict::WrongAnswer => print_diff(&answer, &stdout),
+            Verdict::RuntimeError(_)
+            | Verdict::TimeLimitExceeded
+            | Verdict::MemoryLimitExceeded => {
+                // 制限を超えた場合は出力が途中までなので、差分は表示しない
+                if let Verdict::RuntimeError(_) = verdict {
+                    print_diff(&answer, &stdout);
+                }
+                if !stderr.is_empty() {
+                    println!("  stderr:");
+                    for line in String::from_utf8_lossy(&stderr).lines() {
+                        println!("  {}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    let judged = cases
+        .iter()
+        .filter(|(_, _, answer)| answer.is_some())
+        .count();
+    println!("\n{} / {} AC", accepted, judged);
+
+    Ok(if accepted == judged { 0 } else { 1 })
+}
+
+impl Comparator {
+    pub fn parse(mode: &str) -> Fallible<Comparator> {
+        match mode {
+            "exact" => Ok(Comparator::Exact),
+            "whitespace" => Ok(Comparator::Whitespace),
+            "float" => Ok(Comparator::Float(DEFAULT_EPS)),
+            mode => match mode.strip_prefix("float=").map(str::parse::<f64>) {
+                Some(Ok(eps)) if eps >= 0.0 => Ok(Comparator::Float(eps)),
+                _ => bail!(
+                    "invalid comparison mode: {} (expected exact, whitespace or float[=<eps>])",
+                    mode
+                ),
+            },
+        }
+    }
+
+    /// 出力が期待する出力と一致するか。
+    pub fn matches(self, output: &[u8], answer: &[u8]) -> bool {
+        let eps = match self {
+            Comparator::Exact => return output == answer,
+            Comparator::Whitespace => None,
+            Comparator::Float(eps) => Some(eps),
+        };
+
+        let output = String::from_utf8_lossy(output);
+        let answer = String::from_utf8_lossy(answer);
+        let mut output = output.split_whitespace();
+        let mut answer = answer.split_whitespace();
+        loop {
+            match (output.next(), answer.next()) {
+                (None, None) => return true,
+                (Some(a), Some(b)) if a == b => {}
+                (Some(a), Some(b)) => match (eps, a.parse::<f64>(), b.parse::<f64>()) {
+                    (Some(eps), Ok(a), Ok(b)) => {
+                        let error = (a - b).abs();
+                        if !(error <= eps || error <= eps * b.abs()) {
+                            return false;
+                        }
+                    }
+                    _ => return false,
+                },
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Accepted => write!(f, "AC"),
+            Verdict::WrongAnswer => write!(f, "WA"),
+            Verdict::RuntimeError(code) => write!(f, "RE (exit code {})", code),
+            Verdict::TimeLimitExceeded => write!(f, "TLE"),
+            Verdict::MemoryLimitExceeded => write!(f, "MLE"),
+        }
+    }
+}
+
+/// ディレクトリにある `*.in` と、それに対応する `*.out` (あれば) を名前順に並べる。
+fn find_cases(dir: &Path) -> Fallible<Vec<(String, PathBuf, Option<PathBuf>)>> {
+    let mut cases = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let input = entry?.path();
+        if input.extension().is_none_or(|ext| ext != "in") {
+            continue;
+        }
+
+        let name = input.file_stem().unwrap().to_string_lossy().into_owned();
+        let answer = input.with_extension("out");
+        let answer = if answer.is_file() { Some(answer) } else { None };
+        cases.push((name, input, answer));
+    }
+    cases.sort();
+
+    Ok(cases)
+}
+
+/// 期待する出力と実際の出力の差分を表示する。
+pub fn print_diff(answer: &[u8], output: &[u8]) {
+    let answer = String::from_utf8_lossy(answer);
+    let output = String::from_utf8_lossy(output);
+    let diff = TextDiff::from_lines(&*answer, &*output);
+    for line in diff
+        .unified_diff()
+        .header("expected", "actual")
+        .to_string()
+        .lines()
+    {
+        println!("  {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Comparator;
+
+    fn matches(comparator: &str, output: &str, answer: &str) -> bool {
+        Comparator::parse(comparator)
+            .unwrap()
+            .matches(output.as_bytes(), answer.as_bytes())
+    }
+
+    #[test]
+    fn exact_and_whitespace() {
+        assert!(matches("exact", "1 2\n", "1 2\n"));
+        assert!(!matches("exact", "1 2", "1 2\n"));
+        assert!(matches("whitespace", "1  2", "1 2\n"));
+        assert!(!matches("whitespace", "1 2 3", "1 2"));
+        assert!(!matches("whitespace", "1.0", "1"));
+    }
+
+    #[test]
+    fn float_tolerance() {
+        // 既定の誤差は 1e-6
+        assert!(matches("float", "0.3333333", "0.333333333"));
+        assert!(!matches("float", "0.33", "0.333333333"));
+        // 絶対誤差か相対誤差のどちらかが収まっていればよい
+        assert!(matches("float=1e-3", "1000000.5", "1000000"));
+        assert!(!matches("float=1e-9", "1000000.5", "1000000"));
+        assert!(matches("float=0.5", "1.4", "1"));
+        assert!(!matches("float=0.5", "1.6", "1"));
+        // 数でない単語はそのまま比べる
+        assert!(matches("float", "Yes 1.0000001", "Yes 1"));
+        assert!(!matches("float", "No 1", "Yes 1"));
+        assert!(!matches("float", "nan", "1"));
+    }
+
+    #[test]
+    fn parse_comparator() {
+        assert!(Comparator::parse("float=1e-9").is_ok());
+        assert!(Comparator::parse("float=-1").is_err());
+        assert!(Comparator::parse("fuzzy").is_err());
+    }
+}
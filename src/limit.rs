@@ -0,0 +1,332 @@
+//! 実行するプログラムの時間とメモリの制限。
+//!
+//! 制限はビルドには適用せず、ビルドした実行ファイルにだけ適用する。Unix では子プロセスに rlimit
+//! (`RLIMIT_CPU` と `RLIMIT_AS`) を設定したうえで、監視のループで実時間と常駐メモリ量を確認し、制限を
+//! 超えたら子プロセスを終了させる。
+//!
+//! メモリ制限を超えたかどうかは次のいずれかで判断する。
+//!
+//! - 監視のループで常駐メモリ量が制限を超えたのを見つけた
+//! - 最大常駐メモリ量が制限以上だった
+//! - メモリ制限がある状態で、プログラムが SIGABRT で異常終了した (`RLIMIT_AS` によってメモリの確保に
+//!   失敗すると、Rust のプログラムは abort する)
+//! - プログラムが SIGSEGV などのシグナルで異常終了し、最大常駐メモリ量が制限の半分以上だった (`Vec`
+//!   などは容量を倍々に増やすので、確保に失敗した時点の常駐メモリ量は制限の半分程度になりうる)
+//!
+//! メモリ量に関係なく SIGSEGV などで異常終了した場合は通常の終了として扱う。ただし Rust のプログラムは
+//! スタックオーバーフローでも abort するので、メモリ制限がある状態ではこれもメモリ制限超過になる。
+
+use failure::{bail, Fallible};
+use std::fmt;
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 時間制限を超えた場合の rust-runner の終了コード。
+pub const TIME_LIMIT_EXCEEDED_CODE: i32 = 123;
+
+/// メモリ制限を超えた場合の rust-runner の終了コード。
+pub const MEMORY_LIMIT_EXCEEDED_CODE: i32 = 124;
+
+/// 実行するプログラムの制限。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// 実時間の制限。
+    pub time: Option<Duration>,
+    /// 常駐メモリ量の制限 (バイト)。
+    pub memory: Option<u64>,
+}
+
+/// プログラムがどのように終了したか。
+#[derive(Debug, Clone, Copy)]
+pub enum Termination {
+    Exited(ExitStatus),
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+}
+
+/// 制限のもとでプログラムを実行した結果。
+pub struct Outcome {
+    pub termination: Termination,
+    pub elapsed: Duration,
+    /// 最大の常駐メモリ量 (バイト)。計測できない環境では `None` になる。
+    pub max_rss: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.time.is_none() && self.memory.is_none()
+    }
+
+    /// `2`、`2.5s`、`500ms` のような時間の指定をパースする。単位を省略した場合は秒とする。
+    pub fn parse_time(time: &str) -> Fallible<Duration> {
+        let time = time.trim();
+        let (value, scale) = match (time.strip_suffix("ms"), time.strip_suffix('s')) {
+            (Some(value), _) => (value, 1e-3),
+            (None, Some(value)) => (value, 1.0),
+            (None, None) => (time, 1.0),
+        };
+        match value.trim().parse::<f64>() {
+            Ok(value) if value > 0.0 && value.is_finite() => {
+                Ok(Duration::from_secs_f64(value * scale))
+            }
+            _ => bail!("invalid time limit: {}", time),
+        }
+    }
+
+    /// `256`、`256M`、`1GiB` のようなメモリ量の指定をパースする。単位を省略した場合は MiB とする。
+    pub fn parse_memory(memory: &str) -> Fallible<u64> {
+        let memory = memory.trim();
+        let split = memory
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(memory.len());
+        let (value, unit) = memory.split_at(split);
+        let scale = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "M" | "MB" | "MIB" => 1024.0 * 1024.0,
+            "B" => 1.0,
+            "K" | "KB" | "KIB" => 1024.0,
+            "G" | "GB" | "GIB" => 1024.0 * 1024.0 * 1024.0,
+            _ => bail!("invalid memory limit: {}", memory),
+        };
+        match value.parse::<f64>() {
+            Ok(value) if value > 0.0 && value.is_finite() => Ok((value * scale) as u64),
+            _ => bail!("invalid memory limit: {}", memory),
+        }
+    }
+}
+
+impl Termination {
+    /// rust-runner の終了コードに変換する。
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Termination::Exited(status) => crate::exit_code_of(status),
+            Termination::TimeLimitExceeded => TIME_LIMIT_EXCEEDED_CODE,
+            Termination::MemoryLimitExceeded => MEMORY_LIMIT_EXCEEDED_CODE,
+        }
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Termination::Exited(status) => {
+                write!(f, "exited with code {}", crate::exit_code_of(*status))
+            }
+            Termination::TimeLimitExceeded => write!(f, "time limit exceeded"),
+            Termination::MemoryLimitExceeded => write!(f, "memory limit exceeded"),
+        }
+    }
+}
+
+/// 起動する前のコマンドに rlimit を設定する。監視のループで終了させられなかった場合の備えなので、
+/// 時間の制限には余裕を持たせる。
+#[cfg(unix)]
+pub fn apply(command: &mut Command, limits: &Limits) {
+    use std::io;
+    use std::os::unix::process::CommandExt;
+
+    let limits = *limits;
+    let set_limit = |resource, value: u64| {
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    };
+
+    // fork した後の子プロセスで実行されるので、メモリの確保などをしない処理だけを行う
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(time) = limits.time {
+                set_limit(libc::RLIMIT_CPU, time.as_secs() + 2)?;
+            }
+            if let Some(memory) = limits.memory {
+                set_limit(libc::RLIMIT_AS, memory)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub fn apply(_command: &mut Command, _limits: &Limits) {}
+
+/// 子プロセスの終了を待つ。制限を超えた場合は子プロセスを終了させる。
+pub fn wait(child: &mut Child, limits: &Limits) -> Fallible<Outcome> {
+    let start = Instant::now();
+    loop {
+        // 制限がなければ監視する必要はないので、終了するまでそのまま待つ
+        if let Some((status, max_rss)) = reap(child, !limits.is_empty())? {
+            let elapsed = start.elapsed();
+            let termination = judge_termination(status, max_rss, limits);
+            return Ok(Outcome {
+                termination,
+                elapsed,
+                max_rss,
+            });
+        }
+
+        let elapsed = start.elapsed();
+        let exceeded = if limits.time.is_some_and(|time| elapsed > time) {
+            Some(Termination::TimeLimitExceeded)
+        } else if limits
+            .memory
+            .is_some_and(|memory| current_rss(child).is_some_and(|rss| rss > memory))
+        {
+            Some(Termination::MemoryLimitExceeded)
+        } else {
+            None
+        };
+        if let Some(termination) = exceeded {
+            // すでに終了している場合は失敗するが、いずれにしても終了を待てばよい
+            let _ = child.kill();
+            let max_rss = reap(child, false)?.and_then(|(_, max_rss)| max_rss);
+            return Ok(Outcome {
+                termination,
+                elapsed,
+                max_rss,
+            });
+        }
+
+        // 短いプログラムの時間を正確に測れるよう、はじめは短い間隔で確認する
+        let interval = if elapsed < Duration::from_millis(100) {
+            1
+        } else {
+            10
+        };
+        thread::sleep(Duration::from_millis(interval));
+    }
+}
+
+/// 終了した子プロセスの終了状態と最大常駐メモリ量から、制限を超えて終了したかを判断する。
+fn judge_termination(status: ExitStatus, max_rss: Option<u64>, limits: &Limits) -> Termination {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        match status.signal() {
+            Some(libc::SIGXCPU) if limits.time.is_some() => return Termination::TimeLimitExceeded,
+            Some(libc::SIGABRT) if limits.memory.is_some() => {
+                return Termination::MemoryLimitExceeded
+            }
+            _ => {}
+        }
+    }
+
+    match (limits.memory, max_rss) {
+        (Some(memory), Some(rss))
+            if !status.success()
+                && (rss >= memory || is_memory_fault(status) && rss >= memory / 2) =>
+        {
+            Termination::MemoryLimitExceeded
+        }
+        _ => Termination::Exited(status),
+    }
+}
+
+/// メモリの確保に失敗したときにも受けうるシグナルで終了したか。
+#[cfg(unix)]
+fn is_memory_fault(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    matches!(
+        status.signal(),
+        Some(libc::SIGSEGV) | Some(libc::SIGKILL) | Some(libc::SIGBUS)
+    )
+}
+
+#[cfg(not(unix))]
+fn is_memory_fault(_status: ExitStatus) -> bool {
+    false
+}
+
+/// 子プロセスが終了していれば、終了状態と最大常駐メモリ量 (バイト) を返す。`nohang` でなければ
+/// 終了するまで待つ。
+#[cfg(unix)]
+fn reap(child: &mut Child, nohang: bool) -> Fallible<Option<(ExitStatus, Option<u64>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = child.id() as libc::pid_t;
+    let options = if nohang { libc::WNOHANG } else { 0 };
+    match unsafe { libc::wait4(pid, &mut status, options, &mut rusage) } {
+        0 => Ok(None),
+        pid if pid < 0 => Err(std::io::Error::last_os_error().into()),
+        _ => {
+            // ru_maxrss は macOS ではバイト、それ以外ではキロバイト単位
+            let max_rss = rusage.ru_maxrss as u64;
+            let max_rss = if cfg!(target_os = "macos") {
+                max_rss
+            } else {
+                max_rss * 1024
+            };
+            Ok(Some((ExitStatus::from_raw(status), Some(max_rss))))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn reap(child: &mut Child, nohang: bool) -> Fallible<Option<(ExitStatus, Option<u64>)>> {
+    let status = if nohang {
+        child.try_wait()?
+    } else {
+        Some(child.wait()?)
+    };
+
+    Ok(status.map(|status| (status, None)))
+}
+
+/// 実行中の子プロセスの常駐メモリ量 (バイト) を得る。
+#[cfg(target_os = "linux")]
+fn current_rss(child: &Child) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", child.id())).ok()?;
+    let rss = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kib: u64 = rss.trim().trim_end_matches("kB").trim().parse().ok()?;
+
+    Some(kib * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_rss(_child: &Child) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Limits;
+    use std::time::Duration;
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn parse_time_units() {
+        assert_eq!(Limits::parse_time("2").unwrap(), Duration::from_secs(2));
+        assert_eq!(
+            Limits::parse_time("2.5s").unwrap(),
+            Duration::from_millis(2500)
+        );
+        assert_eq!(
+            Limits::parse_time("500ms").unwrap(),
+            Duration::from_millis(500)
+        );
+        assert!(Limits::parse_time("0").is_err());
+        assert!(Limits::parse_time("2m").is_err());
+    }
+
+    #[test]
+    fn parse_memory_units() {
+        assert_eq!(Limits::parse_memory("256").unwrap(), 256 * MIB);
+        assert_eq!(Limits::parse_memory("256M").unwrap(), 256 * MIB);
+        assert_eq!(Limits::parse_memory("256MiB").unwrap(), 256 * MIB);
+        assert_eq!(Limits::parse_memory("1g").unwrap(), 1024 * MIB);
+        assert_eq!(Limits::parse_memory("1.5GB").unwrap(), 1536 * MIB);
+        assert_eq!(Limits::parse_memory("64K").unwrap(), 64 * 1024);
+        assert_eq!(Limits::parse_memory("100B").unwrap(), 100);
+        assert!(Limits::parse_memory("1T").is_err());
+        assert!(Limits::parse_memory("M").is_err());
+    }
+}
@@ -1,27 +1,58 @@
-use failure::bail;
-use failure::Fallible;
+mod bench;
+mod bundle;
+mod cache;
+mod compare;
+mod config;
+mod dependency;
+mod eval;
+mod imports;
+mod interactive;
+mod judge;
+mod limit;
+mod manifest;
+mod repl;
+mod stress;
+mod testing;
+mod toolchain;
+mod vendor;
+mod watch;
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::dependency::Dependency;
+use crate::limit::{Limits, Termination};
+use crate::vendor::Vendor;
+use failure::{bail, Fail, Fallible};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::stdin;
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::{stdin, IsTerminal};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::thread;
 use tempfile::Builder;
 
+/// 生成するプロジェクトのパッケージ名。実行ファイル名にもなる。
+const PROJECT_NAME: &str = "rustrunner";
+
 lazy_static! {
     static ref RE_OPTION_COMMENT: Regex =
         Regex::new(r#"^\s*//\s*rust-runner:\s*(?P<option>.*)$"#).unwrap();
-    static ref RE_USE: Regex = Regex::new(r#"^\s*use\s+(?P<crate>[\w\d]+)"#).unwrap();
+    static ref RE_PROFILE_NAME: Regex = Regex::new(r#"^[A-Za-z0-9_-]+$"#).unwrap();
 }
 
 enum SourceFile {
     Path(PathBuf),
     Stdin,
+    /// `-e` で指定されたコード片。
+    Eval(String),
 }
 
 impl SourceFile {
@@ -37,56 +68,321 @@ impl SourceFile {
                 stdin().read_to_string(&mut buf)?;
                 Ok(buf)
             }
+            SourceFile::Eval(snippet) => Ok(eval::wrap_snippet(snippet)),
+        }
+    }
+
+    /// ソースコード中の相対パスの基準になるディレクトリを得る。ファイルでなければ現在のディレクトリにする。
+    fn base_dir(&self) -> Fallible<PathBuf> {
+        let current_dir = env::current_dir()?;
+        match self {
+            SourceFile::Path(p) => Ok(current_dir
+                .join(p)
+                .parent()
+                .map_or(current_dir.clone(), Path::to_path_buf)),
+            SourceFile::Stdin | SourceFile::Eval(_) => Ok(current_dir),
         }
     }
 }
 
 struct Args {
     source_file: SourceFile,
+    program_args: Vec<String>,
+    no_cache: bool,
+    offline: bool,
+    /// インストールされていないツールチェインをインストールする。
+    install_toolchain: bool,
+    /// ソースファイルを監視し、保存されるたびに実行し直す。
+    watch: bool,
+    /// 比較のためにビルドして実行するツールチェイン。
+    toolchains: Vec<String>,
+    /// プログラムの標準入出力をつなぐ対話ジャッジのソースファイル。
+    interactor: Option<PathBuf>,
+    /// ソースコード中のオプション指定を上書きする、コマンドラインでの指定。
+    options: Vec<(OptionType, String)>,
 }
 
 impl Args {
+    /// `rust-runner [<flags>...] [<source>|-] [--] [<args>...]` の形の引数をパースする。
+    ///
+    /// ソースファイル以降の引数はすべて実行するプログラムへそのまま渡す。その直後の `--` は区切りと
+    /// みなして取り除く。ソースファイルを省略するか `-` を指定した場合は標準入力から読み込む。
+    /// `-e <code>` を指定した場合はソースファイルを取らず、残りの引数をすべてプログラムへ渡す。
     fn parse_args(args: &[&str]) -> Fallible<Args> {
-        let source_file = match args.get(1).copied() {
-            Some("-") => SourceFile::Stdin,
-            Some(p) => SourceFile::Path(PathBuf::from(p)),
-            _ => SourceFile::Stdin,
+        let mut rest = args.get(1..).unwrap_or(&[]);
+
+        // ソースファイルより前にあるフラグを読む
+        let mut no_cache = false;
+        let mut offline = false;
+        let mut install_toolchain = false;
+        let mut watch = false;
+        let mut toolchains = Vec::new();
+        let mut interactor = None;
+        let mut eval = None;
+        let mut options = Vec::new();
+        loop {
+            match rest {
+                ["--no-cache", tail @ ..] => {
+                    no_cache = true;
+                    rest = tail;
+                }
+                ["--offline", tail @ ..] => {
+                    offline = true;
+                    rest = tail;
+                }
+                ["--install-toolchain", tail @ ..] => {
+                    install_toolchain = true;
+                    rest = tail;
+                }
+                ["--watch", tail @ ..] => {
+                    watch = true;
+                    rest = tail;
+                }
+                ["--toolchains", list, tail @ ..] => {
+                    toolchains = list
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|toolchain| !toolchain.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    rest = tail;
+                }
+                ["--interactor", path, tail @ ..] => {
+                    interactor = Some(PathBuf::from(path));
+                    rest = tail;
+                }
+                ["--release", tail @ ..] => {
+                    options.push((OptionType::Profile, "release".to_string()));
+                    rest = tail;
+                }
+                ["--profile", profile, tail @ ..] => {
+                    options.push((OptionType::Profile, profile.to_string()));
+                    rest = tail;
+                }
+                ["--time-limit", time, tail @ ..] => {
+                    options.push((OptionType::TimeLimit, time.to_string()));
+                    rest = tail;
+                }
+                ["--memory-limit", memory, tail @ ..] => {
+                    options.push((OptionType::MemoryLimit, memory.to_string()));
+                    rest = tail;
+                }
+                ["--edition", edition, tail @ ..] => {
+                    options.push((OptionType::Edition, edition.to_string()));
+                    rest = tail;
+                }
+                ["-e", snippet, tail @ ..] | ["--eval", snippet, tail @ ..] => {
+                    eval = Some(snippet.to_string());
+                    rest = tail;
+                }
+                [flag, ..] if flag.starts_with('-') && *flag != "-" && *flag != "--" => {
+                    bail!("unknown flag: {}", flag)
+                }
+                _ => break,
+            }
+        }
+
+        let (source_file, rest) = match (eval, rest) {
+            (Some(snippet), rest) => (SourceFile::Eval(snippet), rest),
+            (None, rest) => Args::parse_source_file(rest),
+        };
+
+        // ソースファイル直後の `--` は区切りなので取り除く
+        let rest = match rest {
+            ["--", rest @ ..] => rest,
+            rest => rest,
         };
+        let program_args = rest.iter().map(|&arg| arg.to_string()).collect();
+
+        Ok(Args {
+            source_file,
+            program_args,
+            no_cache,
+            offline,
+            install_toolchain,
+            watch,
+            toolchains,
+            interactor,
+            options,
+        })
+    }
+
+    /// サブコマンド固有のフラグを除いた残りの引数を、通常の実行と同じようにパースする。
+    ///
+    /// サブコマンドでは使えないフラグを拒否し、`--offline` などの指定を設定に反映する。
+    fn parse_subcommand_args(name: &str, args: &[&str], config: &mut Config) -> Fallible<Args> {
+        let args: Vec<&str> = iter::once(name).chain(args.iter().copied()).collect();
+        let args = Args::parse_args(&args)?;
+        if args.watch || !args.toolchains.is_empty() || args.interactor.is_some() {
+            bail!(
+                "--watch, --toolchains and --interactor cannot be used with `rust-runner {}`.",
+                name
+            );
+        }
+        config.offline |= args.offline;
+        config.install_toolchain |= args.install_toolchain;
+
+        Ok(args)
+    }
+
+    /// 実行時間を測るサブコマンドの引数を `parse_subcommand_args` と同じようにパースする。
+    ///
+    /// スクリプトでもコマンドラインでもプロファイルが指定されていなければ release でビルドする。
+    fn parse_timing_subcommand_args(
+        name: &str,
+        args: &[&str],
+        config: &mut Config,
+    ) -> Fallible<Args> {
+        let args = Args::parse_subcommand_args(name, args, config)?;
+        config.default_profile = Some("release".to_string());
+
+        Ok(args)
+    }
 
-        Ok(Args { source_file })
+    /// ソースファイルの指定を読み、残りの引数とあわせて返す。
+    fn parse_source_file<'a>(args: &'a [&'a str]) -> (SourceFile, &'a [&'a str]) {
+        match args {
+            ["--", rest @ ..] => (SourceFile::Stdin, rest),
+            ["-", rest @ ..] => (SourceFile::Stdin, rest),
+            [p, rest @ ..] => (SourceFile::Path(PathBuf::from(p)), rest),
+            [] => (SourceFile::Stdin, &[]),
+        }
     }
 }
 
+/// ソースコードから読み取ったプロジェクトの設定。
+///
+/// `Debug` による表現はキャッシュのキーの一部になるので、ビルド結果に影響する設定はすべてここに含める。
+#[derive(Debug, Clone)]
 struct Context {
     toolchain: String,
-    imports: HashSet<String>,
+    /// プロジェクトに追加する依存クレート。インポートから検出したものに `dep` オプションの指定を反映したもの。
+    dependencies: BTreeMap<String, Dependency>,
+    /// `dev-dep` オプションで指定された、テストでだけ使う依存クレート。
+    dev_dependencies: BTreeMap<String, Dependency>,
+    /// スクリプトに埋め込まれた Cargo.toml の断片。
+    manifest: Option<String>,
+    /// オフラインモードで使う、ローカルに保存したクレートのディレクトリ。
+    vendor_dir: Option<PathBuf>,
+    /// rust-toolchain.toml で指定するコンポーネント。
+    components: Vec<String>,
+    /// rust-toolchain.toml で指定するターゲット。
+    targets: Vec<String>,
+    /// パッケージのエディション。
+    edition: String,
+    /// ビルドに使うプロファイル。
+    profile: String,
+    /// プロファイルに設定する最適化レベル。
+    opt_level: Option<String>,
+    /// rustc に渡す追加のフラグ。`target-cpu` オプションの指定もここに含める。
+    rustflags: Vec<String>,
+    /// 実行するプログラムの時間とメモリの制限。ビルド結果には影響しないので、キャッシュのキーには含めない。
+    limits: Limits,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum OptionType {
     Toolchain,
+    Components,
+    Targets,
+    Dep,
+    DevDep,
+    Edition,
+    Profile,
+    OptLevel,
+    Rustflags,
+    TargetCpu,
+    TimeLimit,
+    MemoryLimit,
 }
 
 impl OptionType {
     fn parse(name: &str) -> Option<OptionType> {
         match name {
             "toolchain" => Some(OptionType::Toolchain),
+            "components" => Some(OptionType::Components),
+            "targets" => Some(OptionType::Targets),
+            "dep" => Some(OptionType::Dep),
+            "dev-dep" => Some(OptionType::DevDep),
+            "edition" => Some(OptionType::Edition),
+            "profile" => Some(OptionType::Profile),
+            "opt-level" => Some(OptionType::OptLevel),
+            "rustflags" => Some(OptionType::Rustflags),
+            "target-cpu" => Some(OptionType::TargetCpu),
+            "time-limit" => Some(OptionType::TimeLimit),
+            "memory-limit" => Some(OptionType::MemoryLimit),
             _ => None,
         }
     }
 }
 
 impl Context {
-    pub fn parse(content: &str) -> Fallible<Context> {
-        let options = Context::gather_options(content)?;
+    /// ソースコードから設定を読み取る。`base_dir` は埋め込まれた Cargo.toml の相対パスの基準になる
+    /// ディレクトリ。`overrides` はソースコード中のオプション指定より後に指定されたものとして扱う。
+    pub fn parse(
+        content: &str,
+        base_dir: &Path,
+        config: &Config,
+        overrides: &[(OptionType, String)],
+    ) -> Fallible<Context> {
+        let content = blank_shebang(content);
+        let (content, frontmatter) = manifest::split_frontmatter(&content)?;
+        let mut options = Context::gather_options(&content)?;
+        for (option_type, value) in overrides {
+            options
+                .entry(*option_type)
+                .or_insert_with(Vec::new)
+                .push(value.clone());
+        }
         let toolchain = Context::parse_toolchain(&options).to_string();
-        let imports = Context::parse_imports(content);
+        let components = Context::parse_list(&options, OptionType::Components);
+        let targets = Context::parse_list(&options, OptionType::Targets);
+        // 以降でツールチェインの rustc を使うので、先にインストールされているか確認する
+        toolchain::ensure_installed(&toolchain, &components, &targets, config.install_toolchain)?;
+        let profile = Context::parse_profile(&options, config)?;
+        let opt_level = Context::parse_opt_level(&options)?;
+        let rustflags = Context::parse_rustflags(&options);
+        let limits = Context::parse_limits(&options)?;
+        let manifest = Context::parse_manifest(&content, frontmatter, base_dir)?;
+        // 埋め込まれた Cargo.toml で指定されたクレートは、インポートから検出しても追加しない
+        let mut imports = Context::parse_imports(&content);
+        if let Some(manifest) = &manifest {
+            let declared = manifest::dependency_names(manifest);
+            imports.retain(|import| !declared.contains(import));
+        }
+        let mut dependencies = Context::parse_dependencies(&options, &imports, config)?;
+        let mut dev_dependencies = Context::parse_dev_dependencies(&options)?;
+        // テストのためのインポートから検出したクレートは、dev-dependencies として指定されていればそちらにだけ加える
+        dependencies.retain(|name, _| !dev_dependencies.contains_key(name));
+        let edition = Context::parse_edition(&options, manifest.as_deref(), &toolchain)?;
+        let vendor_dir = if config.offline {
+            let vendor = Vendor::open(config)?;
+            Context::resolve_offline(&mut dependencies, &vendor)?;
+            Context::resolve_offline(&mut dev_dependencies, &vendor)?;
+            Some(vendor.dir().to_path_buf())
+        } else {
+            None
+        };
 
-        Ok(Context { toolchain, imports })
+        Ok(Context {
+            toolchain,
+            components,
+            targets,
+            dependencies,
+            dev_dependencies,
+            manifest,
+            vendor_dir,
+            edition,
+            profile,
+            opt_level,
+            rustflags,
+            limits,
+        })
     }
 
-    /// プログラム先頭のコメント行にあるオプション指定をパースし、 OptionType の配列にする
-    fn gather_options(content: &str) -> Fallible<HashMap<OptionType, String>> {
+    /// プログラム先頭のコメント行にあるオプション指定をパースし、 OptionType ごとに指定された値を
+    /// 出現順に並べる
+    fn gather_options(content: &str) -> Fallible<HashMap<OptionType, Vec<String>>> {
         let mut options = HashMap::new();
         for line in content.lines() {
             if line.trim() == "" {
@@ -105,8 +401,8 @@ impl Context {
             };
 
             let option_strs = captures.name("option").unwrap().as_str().split(';');
-            for option_str in option_strs {
-                let mut name_value = option_str.splitn(2, '=').fuse();
+            for option_str in option_strs.filter(|s| !s.trim().is_empty()) {
+                let mut name_value = option_str.splitn(2, '=').map(str::trim).fuse();
                 let name = name_value.next();
                 let value = name_value.next();
                 let (name, value) = match (name, value) {
@@ -116,7 +412,10 @@ impl Context {
 
                 match OptionType::parse(name) {
                     Some(option_type) => {
-                        options.insert(option_type, value.into());
+                        options
+                            .entry(option_type)
+                            .or_insert_with(Vec::new)
+                            .push(value.into());
                     }
                     None => bail!("unknown option: {}", name),
                 }
@@ -126,108 +425,668 @@ impl Context {
         Ok(options)
     }
 
-    fn parse_toolchain(options: &HashMap<OptionType, String>) -> &str {
+    /// 一つしか値を取らないオプションの値を得る。複数回指定された場合は最後のものを使う。
+    fn last_option(options: &HashMap<OptionType, Vec<String>>, option: OptionType) -> Option<&str> {
         options
-            .get(&OptionType::Toolchain)
+            .get(&option)
+            .and_then(|values| values.last())
             .map(String::as_str)
-            .unwrap_or("stable")
     }
 
-    fn parse_imports(content: &str) -> HashSet<String> {
-        let mut set = HashSet::new();
-        for line in content.lines() {
-            if let Some(captures) = RE_USE.captures(line) {
-                set.insert(captures.name("crate").unwrap().as_str().into());
+    fn parse_toolchain(options: &HashMap<OptionType, Vec<String>>) -> &str {
+        Context::last_option(options, OptionType::Toolchain).unwrap_or("stable")
+    }
+
+    /// カンマ区切りの値を取るオプションの値をすべて並べる。
+    fn parse_list(options: &HashMap<OptionType, Vec<String>>, option: OptionType) -> Vec<String> {
+        options
+            .get(&option)
+            .into_iter()
+            .flatten()
+            .flat_map(|values| values.split(','))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// エディションを決め、ツールチェインで使えるか確認する。
+    ///
+    /// オプションでの指定、埋め込まれた Cargo.toml での指定の順に優先する。どちらもなければ、古い
+    /// ツールチェインでもビルドできるよう、`cargo init` の既定値ではなくツールチェインが対応している最新の
+    /// エディションにする。
+    fn parse_edition(
+        options: &HashMap<OptionType, Vec<String>>,
+        manifest: Option<&str>,
+        toolchain: &str,
+    ) -> Fallible<String> {
+        let edition = Context::last_option(options, OptionType::Edition)
+            .map(str::to_string)
+            .or_else(|| manifest.and_then(manifest::edition));
+        let edition = match edition {
+            Some(edition) => edition,
+            None => return toolchain::latest_edition(toolchain),
+        };
+        toolchain::check_edition(&edition, toolchain)?;
+
+        Ok(edition)
+    }
+
+    fn parse_profile(
+        options: &HashMap<OptionType, Vec<String>>,
+        config: &Config,
+    ) -> Fallible<String> {
+        let default_profile = config.default_profile.as_deref().unwrap_or("dev");
+        let profile = Context::last_option(options, OptionType::Profile).unwrap_or(default_profile);
+        if !RE_PROFILE_NAME.is_match(profile) {
+            bail!("invalid profile name: {}", profile);
+        }
+
+        Ok(profile.to_string())
+    }
+
+    fn parse_opt_level(options: &HashMap<OptionType, Vec<String>>) -> Fallible<Option<String>> {
+        match Context::last_option(options, OptionType::OptLevel) {
+            Some(level @ ("0" | "1" | "2" | "3" | "s" | "z")) => Ok(Some(level.to_string())),
+            Some(level) => bail!("invalid opt-level: {} (expected 0, 1, 2, 3, s or z)", level),
+            None => Ok(None),
+        }
+    }
+
+    /// `rustflags` オプションで指定されたフラグを空白で区切ってすべて並べ、`target-cpu` の指定を加える。
+    fn parse_rustflags(options: &HashMap<OptionType, Vec<String>>) -> Vec<String> {
+        let mut rustflags: Vec<String> = options
+            .get(&OptionType::Rustflags)
+            .into_iter()
+            .flatten()
+            .flat_map(|flags| flags.split_whitespace())
+            .map(str::to_string)
+            .collect();
+        if let Some(cpu) = Context::last_option(options, OptionType::TargetCpu) {
+            rustflags.push("-C".to_string());
+            rustflags.push(format!("target-cpu={}", cpu));
+        }
+
+        rustflags
+    }
+
+    fn parse_limits(options: &HashMap<OptionType, Vec<String>>) -> Fallible<Limits> {
+        let time = Context::last_option(options, OptionType::TimeLimit)
+            .map(Limits::parse_time)
+            .transpose()?;
+        let memory = Context::last_option(options, OptionType::MemoryLimit)
+            .map(Limits::parse_memory)
+            .transpose()?;
+
+        Ok(Limits { time, memory })
+    }
+
+    /// インポートから検出したクレートに、`dep` オプションで指定されたクレートを追加・上書きする。
+    ///
+    /// インポートしたクレートは、設定の対応表にあればそれにしたがってパッケージを決める。
+    fn parse_dependencies(
+        options: &HashMap<OptionType, Vec<String>>,
+        imports: &BTreeSet<String>,
+        config: &Config,
+    ) -> Fallible<BTreeMap<String, Dependency>> {
+        let mut dependencies: BTreeMap<_, _> = imports
+            .iter()
+            .map(|import| {
+                let dependency = match config.crates.get(import) {
+                    Some(mapping) => Dependency::from_mapping(import, mapping),
+                    None => Dependency::new(import),
+                };
+                (import.clone(), dependency)
+            })
+            .collect();
+
+        for spec in options.get(&OptionType::Dep).into_iter().flatten() {
+            let dependency = Dependency::parse(spec)?;
+            dependencies.insert(dependency.name.clone(), dependency);
+        }
+
+        Ok(dependencies)
+    }
+
+    fn parse_dev_dependencies(
+        options: &HashMap<OptionType, Vec<String>>,
+    ) -> Fallible<BTreeMap<String, Dependency>> {
+        let mut dev_dependencies = BTreeMap::new();
+        for spec in options.get(&OptionType::DevDep).into_iter().flatten() {
+            let dependency = Dependency::parse(spec)?;
+            dev_dependencies.insert(dependency.name.clone(), dependency);
+        }
+
+        Ok(dev_dependencies)
+    }
+
+    /// オフラインモードのため、依存クレートがすべてローカルにあることを確認する。
+    ///
+    /// パッケージ名はローカルにあるものの正確な名前に置き換える。
+    fn resolve_offline(
+        dependencies: &mut BTreeMap<String, Dependency>,
+        vendor: &Vendor,
+    ) -> Fallible<()> {
+        for dependency in dependencies.values_mut() {
+            let package = vendor.resolve(dependency)?;
+            if package != dependency.name {
+                dependency.package = Some(package);
             }
         }
 
-        for special in &["std", "crate", "self", "super"] {
-            set.remove(*special);
+        Ok(())
+    }
+
+    /// フロントマターか先頭のドキュメントコメントに埋め込まれた Cargo.toml の断片を得る。
+    ///
+    /// プロジェクトは一時ディレクトリに作るので、依存クレートの相対パスは `base_dir` からのパスに書き換える。
+    fn parse_manifest(
+        content: &str,
+        frontmatter: Option<String>,
+        base_dir: &Path,
+    ) -> Fallible<Option<String>> {
+        let manifest = match (frontmatter, manifest::find_doc_manifest(content)?) {
+            (Some(_), Some(_)) => {
+                bail!("both frontmatter and ```cargo block are specified. use either one.")
+            }
+            (frontmatter, doc_manifest) => frontmatter.or(doc_manifest),
+        };
+        match manifest {
+            Some(manifest) => {
+                manifest::validate(&manifest)?;
+                Ok(Some(manifest::resolve_paths(&manifest, base_dir)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn parse_imports(content: &str) -> BTreeSet<String> {
+        imports::find_external_crates(content)
+    }
+}
+
+/// プロジェクトの準備段階で起きた失敗。
+///
+/// 実行したプログラム自身の終了コードと区別できるよう、それぞれ専用の終了コードで rust-runner を終了する。
+/// rust-runner 自身のその他のエラー (オプションの誤りなど) では終了コード 1 で終了する。
+///
+/// | 終了コード | 意味 |
+/// |-----------|------|
+/// | 121 | `cargo init` によるプロジェクトの作成に失敗した |
+/// | 122 | プログラムのビルドに失敗した |
+/// | 123 | プログラムが時間制限を超えた (`limit::TIME_LIMIT_EXCEEDED_CODE`) |
+/// | 124 | プログラムがメモリ制限を超えた (`limit::MEMORY_LIMIT_EXCEEDED_CODE`) |
+///
+/// プログラムが実行された場合はその終了コードをそのまま返す。シグナルで終了した場合は 128 + シグナル番号
+/// を返す。
+#[derive(Debug)]
+enum ProjectError {
+    InitFailed,
+    BuildFailed,
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectError::InitFailed => write!(f, "failed to init cargo project."),
+            ProjectError::BuildFailed => write!(f, "failed to build the program."),
         }
+    }
+}
+
+impl Fail for ProjectError {}
 
-        set
+impl ProjectError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            ProjectError::InitFailed => 121,
+            ProjectError::BuildFailed => 122,
+        }
     }
 }
 
-fn main() -> Fallible<()> {
-    // 引数をパースする。
+fn main() {
+    let code = match run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            e.downcast_ref::<ProjectError>()
+                .map(ProjectError::exit_code)
+                .unwrap_or(1)
+        }
+    };
+
+    process::exit(code);
+}
+
+fn run() -> Fallible<i32> {
+    // 引数をパースする。サブコマンドであればそちらを実行する。
     let args = env::args().collect_vec();
     let args = args.iter().map(String::as_str).collect_vec();
+    match args.get(1..).unwrap_or(&[]) {
+        ["cache", rest @ ..] => return cache::command(rest),
+        ["vendor", rest @ ..] => return vendor::command(rest, &Config::load()?),
+        ["repl", rest @ ..] => return repl::command(rest, &Config::load()?),
+        ["test", rest @ ..] => return testing::command(rest, Config::load()?),
+        ["bench", rest @ ..] => return bench::command(rest, Config::load()?),
+        ["judge", rest @ ..] => return judge::command(rest, Config::load()?),
+        ["stress", rest @ ..] => return stress::command(rest, Config::load()?),
+        ["bundle", rest @ ..] => return bundle::command(rest, Config::load()?),
+        _ => {}
+    }
     let args = Args::parse_args(&args)?;
 
     // 内容を読み込み、インポートを抽出する。
+    let mut config = Config::load()?;
+    config.offline |= args.offline;
+    config.install_toolchain |= args.install_toolchain;
+    if !args.toolchains.is_empty() {
+        if args.watch || args.interactor.is_some() {
+            bail!("--watch and --interactor cannot be used with --toolchains.");
+        }
+        return compare::run(&args, &config);
+    }
+    if args.watch && args.interactor.is_some() {
+        bail!("--watch cannot be used with --interactor.");
+    }
+    if args.watch {
+        return match &args.source_file {
+            SourceFile::Path(path) => watch::run(path, &args, &config),
+            _ => bail!("--watch requires a source file."),
+        };
+    }
     let content = args.source_file.read_content()?;
-    let context = Context::parse(&content)?;
+    let context = Context::parse(
+        &content,
+        &args.source_file.base_dir()?,
+        &config,
+        &args.options,
+    )?;
 
-    // 一時ディレクトリにプロジェクトを作成し、そこへ移動。
+    // 一時ディレクトリはスコープを抜けると削除されるので、実行が終わるまで保持しておく。
     let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
-    let old_current = env::current_dir()?;
-    env::set_current_dir(tmpdir.path())?;
+    let binary = prepare_binary(tmpdir.path(), &content, &context, args.no_cache)?;
+
+    if let Some(interactor) = &args.interactor {
+        return interactive::run(&binary, interactor, &args, &context, &config);
+    }
+
+    let mut command = Command::new(binary);
+    command.args(&args.program_args);
+    if context.limits.is_empty() {
+        return Ok(exit_code_of(command.status()?));
+    }
+
+    limit::apply(&mut command, &context.limits);
+    let outcome = limit::wait(&mut command.spawn()?, &context.limits)?;
+    if let Termination::TimeLimitExceeded | Termination::MemoryLimitExceeded = outcome.termination {
+        eprintln!("{}.", outcome.termination);
+    }
+
+    Ok(outcome.termination.exit_code())
+}
+
+/// キャッシュにビルド済みの実行ファイルがあればそのパスを返す。なければ指定したディレクトリでビルドして
+/// キャッシュに保存し、そのパスを返す。`no_cache` のときはキャッシュを使わずにビルドする。
+///
+/// パスで指定した依存クレートはその中身がキャッシュのキーに含まれず、変更しても気付けないので、
+/// 埋め込まれた Cargo.toml にそれがあればキャッシュを使わない。
+fn prepare_binary(
+    project_dir: &Path,
+    content: &str,
+    context: &Context,
+    no_cache: bool,
+) -> Fallible<PathBuf> {
+    let cache = Cache::open()?;
+    let has_path_dependencies = context
+        .manifest
+        .as_deref()
+        .is_some_and(manifest::has_path_dependencies);
+    if no_cache || has_path_dependencies {
+        return build_binary(project_dir, content, context, &cache);
+    }
+
+    let key = cache::cache_key(content, context)?;
+    match cache.lookup(&key) {
+        Some(binary) => Ok(binary),
+        None => {
+            let binary = build_binary(project_dir, content, context, &cache)?;
+            cache.store(&key, &binary)
+        }
+    }
+}
+
+/// 指定したディレクトリにプロジェクトを作成してビルドし、できた実行ファイルのパスを返す。
+fn build_binary(
+    project_dir: &Path,
+    content: &str,
+    context: &Context,
+    cache: &Cache,
+) -> Fallible<PathBuf> {
+    init_project(project_dir, content, context)?;
+    build_and_extract(project_dir, context, cache, false)
+}
+
+fn init_project(project_dir: &Path, content: &str, context: &Context) -> Fallible<()> {
+    create_project(project_dir, context)?;
+    write_source(project_dir, content)?;
+    add_dependencies(project_dir, context, context.dependencies.values(), false)?;
+    add_dependencies(project_dir, context, context.dev_dependencies.values(), true)?;
+
+    let cargo_toml = project_dir.join("Cargo.toml");
+
+    // 独自のプロファイルは、埋め込まれた Cargo.toml で定義されていなければ release を継承する
+    if !is_builtin_profile(&context.profile) {
+        let profile = format!("[profile.{}]\ninherits = \"release\"\n", context.profile);
+        manifest::merge_into(&cargo_toml, &profile)?;
+    }
+
+    // 埋め込まれた Cargo.toml の断片をマージする
+    if let Some(manifest) = &context.manifest {
+        manifest::merge_into(&cargo_toml, manifest)?;
+    }
 
-    // プロジェクトを初期化・実行する。
-    let res = init_project(&content, &context).and_then(|_| run_project());
+    // エディションと最適化レベルはオプションでの指定を埋め込まれた Cargo.toml より優先する
+    write_edition(project_dir, context)?;
+    if let Some(opt_level) = &context.opt_level {
+        // 数値の最適化レベルは文字列ではなく整数で書く
+        let opt_level = match opt_level.parse::<u32>() {
+            Ok(level) => level.to_string(),
+            Err(_) => format!("\"{}\"", opt_level),
+        };
+        let profile = format!("[profile.{}]\nopt-level = {}\n", context.profile, opt_level);
+        manifest::merge_into(&cargo_toml, &profile)?;
+    }
 
-    // 成否に関わらず一時ディレクトリを削除する。
-    env::set_current_dir(old_current)?;
+    // rust-toolchain を書き込む
+    write_toolchain(project_dir, context)?;
 
-    res
+    Ok(())
 }
 
-fn init_project(content: &str, context: &Context) -> Fallible<()> {
+/// `cargo init` でプロジェクトを作成し、ビルドの設定をする。
+fn create_project(project_dir: &Path, context: &Context) -> Fallible<()> {
     // cargo init
     let init_success = Command::new("cargo")
         .arg("init")
         .arg("--name")
-        .arg("rustrunner")
+        .arg(PROJECT_NAME)
+        .current_dir(project_dir)
         .status()?
         .success();
     if !init_success {
-        bail!("failed to init cargo project.");
+        return Err(ProjectError::InitFailed.into());
     }
 
+    let mut cargo_config = Vec::new();
+
+    let mut build_config = Vec::new();
+
     // sccache が使える場合は sccache を有効にする
     if let Ok(sccache) = which::which("sccache") {
-        fs::create_dir_all(".cargo")?;
-        let mut s = Vec::new();
-        writeln!(s, r#"[build]"#).unwrap();
-        writeln!(
-            s,
+        build_config.push(format!(
             r#"rustc-wrapper = "{}""#,
             sccache.display().to_string().escape_default()
-        )
-        .unwrap();
-        fs::write(".cargo/config", s)?;
+        ));
+    }
+
+    if !context.rustflags.is_empty() {
+        let rustflags = context
+            .rustflags
+            .iter()
+            .map(|flag| format!(r#""{}""#, flag.escape_default()))
+            .join(", ");
+        build_config.push(format!("rustflags = [{}]", rustflags));
+    }
+
+    if !build_config.is_empty() {
+        writeln!(cargo_config, r#"[build]"#).unwrap();
+        for line in build_config {
+            writeln!(cargo_config, "{}", line).unwrap();
+        }
+    }
+
+    // オフラインモードではローカルに保存したクレートだけを使う
+    if let Some(vendor_dir) = &context.vendor_dir {
+        writeln!(cargo_config, "{}", vendor::cargo_config(vendor_dir)).unwrap();
+    }
+
+    if !cargo_config.is_empty() {
+        fs::create_dir_all(project_dir.join(".cargo"))?;
+        fs::write(project_dir.join(".cargo/config"), cargo_config)?;
     }
 
-    // ソースファイルを置き換える
-    fs::remove_file("src/main.rs")?;
-    let mut f = File::create("src/main.rs")?;
+    Ok(())
+}
+
+/// ソースファイルを置き換える。シバン行とフロントマターはコンパイルできないので取り除く。
+fn write_source(project_dir: &Path, content: &str) -> Fallible<()> {
+    let content = blank_shebang(content);
+    let (content, _) = manifest::split_frontmatter(&content)?;
+    let mut f = File::create(project_dir.join("src/main.rs"))?;
     f.write_all(content.as_bytes())?;
 
-    // 必要なクレートを `cargo add` する
-    for import in &context.imports {
-        eprintln!("adding `{}` to the project", import);
-        let success = Command::new("cargo")
-            .arg("add")
-            .arg(import)
+    Ok(())
+}
+
+/// 先頭のシバン行 (`#!/usr/bin/env rust-runner` など) を空行に置き換える。
+///
+/// 行番号がずれないよう、行そのものは残す。`#![...]` は内部属性なので置き換えない。
+fn blank_shebang(content: &str) -> String {
+    match content.strip_prefix("#!") {
+        Some(rest) if !rest.trim_start().starts_with('[') => {
+            let end = content.find('\n').unwrap_or(content.len());
+            content[end..].to_string()
+        }
+        _ => content.to_string(),
+    }
+}
+
+/// 必要なクレートを `cargo add` する。`dev` のときは dev-dependencies に追加する。
+fn add_dependencies<'a>(
+    project_dir: &Path,
+    context: &Context,
+    dependencies: impl IntoIterator<Item = &'a Dependency>,
+    dev: bool,
+) -> Fallible<()> {
+    for dependency in dependencies {
+        let name = &dependency.name;
+        eprintln!("adding `{}` to the project", name);
+        let mut command = Command::new("cargo");
+        command.arg("add").args(dependency.cargo_add_args());
+        if dev {
+            command.arg("--dev");
+        }
+        let success = command
+            .current_dir(project_dir)
             .status()?
             .success();
         if !success {
-            eprintln!("  ... adding crate `{}` failed, ignoring.", import);
+            // オフラインモードではローカルにあることを確認済みなので、失敗は無視しない
+            if context.vendor_dir.is_some() {
+                bail!("failed to add crate `{}` from the vendor directory.", name);
+            }
+            eprintln!("  ... adding crate `{}` failed, ignoring.", name);
         }
     }
 
-    // rust-toolchain を書き込む
-    fs::write("rust-toolchain", &context.toolchain)?;
-
     Ok(())
 }
 
-fn run_project() -> Fallible<()> {
-    let success = Command::new("cargo").arg("run").status()?.success();
-    if !success {
-        bail!("failed to run the program.");
+/// Cargo.toml にパッケージのエディションを書き込む。
+fn write_edition(project_dir: &Path, context: &Context) -> Fallible<()> {
+    let package = format!("[package]\nedition = \"{}\"\n", context.edition);
+    manifest::merge_into(&project_dir.join("Cargo.toml"), &package)
+}
+
+/// rust-toolchain を書き込む。
+///
+/// コンポーネントやターゲットが指定されている場合は、それらを書ける rust-toolchain.toml の形式にする。
+fn write_toolchain(project_dir: &Path, context: &Context) -> Fallible<()> {
+    let plain = project_dir.join("rust-toolchain");
+    let toml = project_dir.join("rust-toolchain.toml");
+    if context.components.is_empty() && context.targets.is_empty() {
+        fs::write(&plain, &context.toolchain)?;
+        if toml.exists() {
+            fs::remove_file(&toml)?;
+        }
+        return Ok(());
+    }
+
+    let mut toolchain = toml::Table::new();
+    toolchain.insert("channel".into(), context.toolchain.clone().into());
+    if !context.components.is_empty() {
+        toolchain.insert("components".into(), context.components.clone().into());
+    }
+    if !context.targets.is_empty() {
+        toolchain.insert("targets".into(), context.targets.clone().into());
+    }
+    let mut file = toml::Table::new();
+    file.insert("toolchain".into(), toolchain.into());
+    fs::write(&toml, toml::to_string(&file)?)?;
+    if plain.exists() {
+        fs::remove_file(&plain)?;
     }
 
     Ok(())
 }
+
+/// 共有ターゲットディレクトリを使ってプロジェクトをビルドし、できた実行ファイルをプロジェクトの
+/// ディレクトリへ取り出してそのパスを返す。
+fn build_and_extract(
+    project_dir: &Path,
+    context: &Context,
+    cache: &Cache,
+    quiet: bool,
+) -> Fallible<PathBuf> {
+    let target_dir = cache.lock_target_dir(&context.toolchain)?;
+    let built = build_project(project_dir, target_dir.path(), &context.profile, quiet)?;
+    let binary = project_dir.join(built.file_name().unwrap());
+    fs::copy(&built, &binary)?;
+
+    Ok(binary)
+}
+
+/// 指定したターゲットディレクトリへ指定したプロファイルでプロジェクトをビルドし、できた実行ファイルの
+/// パスを返す。
+///
+/// `quiet` のときはビルドの進捗を表示しない (エラーや警告は表示する)。
+fn build_project(
+    project_dir: &Path,
+    target_dir: &Path,
+    profile: &str,
+    quiet: bool,
+) -> Fallible<PathBuf> {
+    // 環境変数などで別の場所が指定されていても実行ファイルを見つけられるよう、出力先を明示する
+    let mut command = Command::new("cargo");
+    command
+        .arg("build")
+        .arg("--target-dir")
+        .arg(target_dir)
+        .args(profile_args(profile))
+        .current_dir(project_dir);
+    if quiet {
+        command.arg("--quiet");
+    }
+    if !command.status()?.success() {
+        return Err(ProjectError::BuildFailed.into());
+    }
+
+    // 組み込みのプロファイルの出力先はプロファイル名と異なる
+    let profile_dir = match profile {
+        "dev" | "test" => "debug",
+        "release" | "bench" => "release",
+        profile => profile,
+    };
+
+    Ok(target_dir
+        .join(profile_dir)
+        .join(format!("{}{}", PROJECT_NAME, env::consts::EXE_SUFFIX)))
+}
+
+/// 指定したプロファイルでビルドする cargo の引数を返す。
+///
+/// `--profile` に対応していない古い cargo でもビルドできるよう、dev と release では使わない。
+fn profile_args(profile: &str) -> Vec<&str> {
+    match profile {
+        "dev" => vec![],
+        "release" => vec!["--release"],
+        profile => vec!["--profile", profile],
+    }
+}
+
+/// cargo が定義しているプロファイルか。
+fn is_builtin_profile(profile: &str) -> bool {
+    matches!(profile, "dev" | "release" | "test" | "bench")
+}
+
+/// プログラムに与える入力を標準入力から読み込む。標準入力が端末の場合は入力を待たず、空の入力にする。
+fn read_program_input() -> Fallible<Vec<u8>> {
+    let mut input = Vec::new();
+    if !stdin().is_terminal() {
+        stdin().read_to_end(&mut input)?;
+    }
+
+    Ok(input)
+}
+
+/// 制限のもとでプログラムに入力を与えて実行し、標準出力と標準エラー出力をすべて受け取る。
+fn run_with_input(
+    binary: &Path,
+    args: &[String],
+    input: &[u8],
+    limits: &Limits,
+) -> Fallible<(limit::Outcome, Vec<u8>, Vec<u8>)> {
+    let mut command = Command::new(binary);
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    limit::apply(&mut command, limits);
+    let mut child = command.spawn()?;
+
+    // パイプが詰まらないよう、入力の書き込みと出力の読み込みはそれぞれ別スレッドで行う
+    let mut child_stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || {
+        // プログラムが入力を読み切らずに終了した場合は書き込みに失敗するが、問題ない
+        let _ = child_stdin.write_all(&input);
+    });
+    let stdout_reader = spawn_reader(child.stdout.take().unwrap());
+    let stderr_reader = spawn_reader(child.stderr.take().unwrap());
+
+    let outcome = limit::wait(&mut child, limits)?;
+    let _ = writer.join();
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    Ok((outcome, stdout, stderr))
+}
+
+/// 別スレッドで読み込みを終わりまで続け、読んだ内容を返す。
+fn spawn_reader(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// 実行したプログラムの終了状態を rust-runner の終了コードに変換する。
+fn exit_code_of(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    // シグナルで終了した場合はシェルの慣習に倣って 128 + シグナル番号とする
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    1
+}
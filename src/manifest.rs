@@ -0,0 +1,283 @@
+//! スクリプトに埋め込まれた Cargo.toml の断片の扱い。
+//!
+//! 次のどちらかの形で書かれた断片を、生成したプロジェクトの Cargo.toml にマージする。
+//!
+//! - 先頭のコメント行にある `//! ```cargo` から `//! ```` までのブロック (cargo-script と同じ形式)
+//! - ファイル先頭の `---cargo` から `---` までのフロントマター
+//!
+//! フロントマターは stable の rustc では受け付けられないので、`src/main.rs` に書き込む前に空行に置き換える。
+
+use failure::{bail, Fallible};
+use std::collections::BTreeSet;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// 依存クレートを指定するテーブルの名前。
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// ファイル先頭のフロントマターを取り出す。
+///
+/// フロントマターを取り除いた (行番号を保つため空行に置き換えた) ソースと、フロントマターの中身を返す。
+pub fn split_frontmatter(content: &str) -> Fallible<(String, Option<String>)> {
+    let lines: Vec<&str> = content.lines().collect();
+
+    // フロントマターより前には空行しか置けない
+    let start = match lines.iter().position(|line| !line.trim().is_empty()) {
+        Some(start) => start,
+        None => return Ok((content.to_string(), None)),
+    };
+
+    let opening = lines[start].trim_end();
+    let dashes = opening.chars().take_while(|&c| c == '-').count();
+    if dashes < 3 {
+        return Ok((content.to_string(), None));
+    }
+    match opening[dashes..].trim() {
+        "" | "cargo" => {}
+        info => bail!("unsupported frontmatter type: {}", info),
+    }
+
+    let closing = &opening[..dashes];
+    let end = match lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_end() == closing)
+    {
+        Some(end) => start + 1 + end,
+        None => bail!("frontmatter is not closed with `{}`.", closing),
+    };
+
+    let manifest = lines[start + 1..end].join("\n");
+    let mut source = "\n".repeat(end + 1);
+    source.extend(lines[end + 1..].iter().flat_map(|line| vec![*line, "\n"]));
+
+    Ok((source, Some(manifest)))
+}
+
+/// 先頭のコメント行にある `//! ```cargo` ブロックの中身を取り出す。
+pub fn find_doc_manifest(content: &str) -> Fallible<Option<String>> {
+    let mut manifest: Option<Vec<&str>> = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if !line.starts_with("//") {
+            // 先頭のコメント行が終わった
+            break;
+        }
+
+        let doc = match line.strip_prefix("//!") {
+            Some(doc) => doc.strip_prefix(' ').unwrap_or(doc),
+            None => continue,
+        };
+        match &mut manifest {
+            None if doc.trim() == "```cargo" => manifest = Some(Vec::new()),
+            None => {}
+            Some(manifest) if doc.trim() == "```" => return Ok(Some(manifest.join("\n"))),
+            Some(manifest) => manifest.push(doc),
+        }
+    }
+
+    if manifest.is_some() {
+        bail!("```cargo block in doc comments is not closed.");
+    }
+
+    Ok(None)
+}
+
+/// Cargo.toml の断片として正しいか確認する。
+pub fn validate(manifest: &str) -> Fallible<()> {
+    let manifest: Table = match manifest.parse() {
+        Ok(manifest) => manifest,
+        Err(e) => bail!("invalid embedded manifest: {}", e),
+    };
+
+    // パッケージ名は実行ファイル名になるので変更させない
+    let package_name = manifest
+        .get("package")
+        .and_then(Value::as_table)
+        .and_then(|package| package.get("name"));
+    if package_name.is_some() {
+        bail!("the package name cannot be changed in the embedded manifest.");
+    }
+
+    Ok(())
+}
+
+/// Cargo.toml の断片で指定されたエディションを得る。
+pub fn edition(manifest: &str) -> Option<String> {
+    let manifest: Table = manifest.parse().ok()?;
+    let edition = manifest.get("package")?.as_table()?.get("edition")?;
+
+    edition.as_str().map(str::to_string)
+}
+
+/// Cargo.toml の断片で指定された依存クレートの、ソースコードから参照するときの名前を得る。
+pub fn dependency_names(manifest: &str) -> BTreeSet<String> {
+    let manifest: Table = match manifest.parse() {
+        Ok(manifest) => manifest,
+        Err(_) => return BTreeSet::new(),
+    };
+
+    dependency_tables(&manifest)
+        .flat_map(|dependencies| dependencies.keys())
+        .map(|name| name.replace('-', "_"))
+        .collect()
+}
+
+/// Cargo.toml の断片でパスを指定した依存クレートがあるか。
+pub fn has_path_dependencies(manifest: &str) -> bool {
+    let manifest: Table = match manifest.parse() {
+        Ok(manifest) => manifest,
+        Err(_) => return false,
+    };
+
+    let has_path = dependency_tables(&manifest)
+        .flat_map(|dependencies| dependencies.values())
+        .any(|dependency| dependency.get("path").is_some());
+
+    has_path
+}
+
+/// `[dependencies]` や `[target.'cfg(..)'.dependencies]` などの依存クレートのテーブルを列挙する。
+fn dependency_tables(manifest: &Table) -> impl Iterator<Item = &Table> {
+    let targets = manifest
+        .get("target")
+        .and_then(Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values().filter_map(Value::as_table));
+
+    iter::once(manifest).chain(targets).flat_map(|table| {
+        DEPENDENCY_TABLES
+            .iter()
+            .filter_map(move |key| table.get(*key).and_then(Value::as_table))
+    })
+}
+
+/// 依存クレートの相対パスで指定された `path` を、`base_dir` からの絶対パスに書き換える。
+pub fn resolve_paths(manifest: &str, base_dir: &Path) -> Fallible<String> {
+    let mut manifest: Table = manifest.parse()?;
+    resolve_dependency_paths(&mut manifest, base_dir);
+    if let Some(Value::Table(targets)) = manifest.get_mut("target") {
+        for (_, target) in targets.iter_mut() {
+            if let Value::Table(target) = target {
+                resolve_dependency_paths(target, base_dir);
+            }
+        }
+    }
+
+    Ok(toml::to_string(&manifest)?)
+}
+
+/// `[dependencies]` などの依存クレートのテーブルにある相対パスを書き換える。
+fn resolve_dependency_paths(table: &mut Table, base_dir: &Path) {
+    for key in DEPENDENCY_TABLES {
+        let dependencies = match table.get_mut(*key) {
+            Some(Value::Table(dependencies)) => dependencies,
+            _ => continue,
+        };
+        for (_, dependency) in dependencies.iter_mut() {
+            if let Some(Value::String(path)) = dependency.get_mut("path") {
+                if Path::new(path.as_str()).is_relative() {
+                    let resolved = base_dir.join(path.as_str());
+                    *path = resolved.to_string_lossy().into_owned();
+                }
+            }
+        }
+    }
+}
+
+/// Cargo.toml に断片をマージする。断片に書かれた値が優先される。
+pub fn merge_into(cargo_toml: &Path, manifest: &str) -> Fallible<()> {
+    let mut base: Table = fs::read_to_string(cargo_toml)?.parse()?;
+    let manifest: Table = manifest.parse()?;
+    merge_table(&mut base, manifest);
+    fs::write(cargo_toml, toml::to_string(&base)?)?;
+
+    Ok(())
+}
+
+/// テーブル同士は再帰的にマージし、それ以外は上書きする。
+fn merge_table(base: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(other)) => merge_table(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_frontmatter_keeps_line_numbers() {
+        let content = "---cargo\n[dependencies]\nrand = \"0.8\"\n---\nfn main() {}\n";
+        let (source, manifest) = split_frontmatter(content).unwrap();
+        assert_eq!(source, "\n\n\n\nfn main() {}\n");
+        assert_eq!(manifest.as_deref(), Some("[dependencies]\nrand = \"0.8\""));
+    }
+
+    #[test]
+    fn split_frontmatter_without_frontmatter() {
+        let content = "// comment\nfn main() {}\n";
+        assert_eq!(
+            split_frontmatter(content).unwrap(),
+            (content.to_string(), None)
+        );
+        assert!(split_frontmatter("---\n[dependencies]\n").is_err());
+        assert!(split_frontmatter("---toml\n---\n").is_err());
+    }
+
+    #[test]
+    fn find_doc_manifest_in_leading_comments() {
+        let content = "\n//! A script.\n//!\n//! ```cargo\n//! [dependencies]\n//! rand = \"0.8\"\n//! ```\nfn main() {}\n";
+        assert_eq!(
+            find_doc_manifest(content).unwrap().as_deref(),
+            Some("[dependencies]\nrand = \"0.8\"")
+        );
+        assert_eq!(
+            find_doc_manifest("fn main() {}\n//! ```cargo\n").unwrap(),
+            None
+        );
+        assert!(find_doc_manifest("//! ```cargo\n//! [dependencies]\n").is_err());
+    }
+
+    #[test]
+    fn resolve_relative_paths() {
+        let manifest = "[dependencies]\na = { path = \"lib/a\" }\nb = { path = \"/abs/b\" }\nc = \"1\"\n\n[target.'cfg(unix)'.dev-dependencies]\nd = { path = \"../d\" }\n";
+        let resolved: Table = resolve_paths(manifest, Path::new("/work"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let path = |table: &Value, name: &str| table[name]["path"].as_str().map(str::to_string);
+        assert_eq!(
+            path(&resolved["dependencies"], "a").as_deref(),
+            Some("/work/lib/a")
+        );
+        assert_eq!(
+            path(&resolved["dependencies"], "b").as_deref(),
+            Some("/abs/b")
+        );
+        assert_eq!(resolved["dependencies"]["c"].as_str(), Some("1"));
+        assert_eq!(
+            path(&resolved["target"]["cfg(unix)"]["dev-dependencies"], "d").as_deref(),
+            Some("/work/../d")
+        );
+    }
+
+    #[test]
+    fn dependency_names_and_paths() {
+        let manifest = "[dependencies]\nnum-bigint = \"0.4\"\n[target.'cfg(unix)'.dependencies]\nmylib = { path = \"mylib\" }\n";
+        let names: Vec<String> = dependency_names(manifest).into_iter().collect();
+        assert_eq!(names, ["mylib", "num_bigint"]);
+        assert!(has_path_dependencies(manifest));
+        assert!(!has_path_dependencies("[dependencies]\nrand = \"0.8\"\n"));
+    }
+}
@@ -0,0 +1,345 @@
+//! `rust-runner repl` による対話的な実行。
+//!
+//! 入力された `use` やアイテム、文をセッションに蓄積し、入力のたびにそれらをすべて含むプログラムを
+//! 一つのプロジェクトでビルドし直して実行する。前回までに表示した出力は表示せず、新しい入力による出力と
+//! 末尾の式の値だけを表示する。ビルドや実行に失敗した入力はセッションに加えない。
+//!
+//! 依存クレートは `Context` によってインポートから検出し、初めて参照されたときにプロジェクトに追加する。
+//! エディションはツールチェインに合わせて決め、ツールチェインを変えたときに書き換える。
+//!
+//! 入力のたびにこれまでの文をすべて実行し直すので、標準出力への書き込みやファイルの操作などの副作用は
+//! 毎回起きる。乱数や時刻のように実行ごとに変わる値は、入力のたびに違う値になる。前回までの出力は長さで
+//! 取り除いているので、以前の文の出力の長さが実行ごとに変わると、新しい出力の表示がずれる。
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::dependency::Dependency;
+use crate::{eval, Context};
+use failure::{bail, Fallible};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::prelude::*;
+use std::io::{self, stdin, stdout};
+use std::process::{Command, Stdio};
+use tempfile::{Builder, TempDir};
+
+/// プログラムの出力のうち、末尾の式の値の前に出力する区切り。
+const VALUE_MARKER: &str = "\u{1}rust-runner-value\u{1}";
+
+const HELP: &str = "\
+:dep <crate>[@<version>][[<features>]]  add a dependency
+:toolchain <toolchain>                 use the toolchain
+:type <expr>                           show the type of the expression
+:show                                  show the current program
+:reset                                 clear the session
+:help                                  show this help
+:quit                                  exit the repl";
+
+/// `rust-runner repl` を実行する。
+pub fn command(args: &[&str], config: &Config) -> Fallible<i32> {
+    if !args.is_empty() {
+        bail!("usage: rust-runner repl");
+    }
+
+    let cache = Cache::open()?;
+    let mut repl = Repl::new(config, &cache)?;
+    eprintln!("rust-runner repl. type :help for help.");
+    while let Some(input) = read_input()? {
+        match repl.eval(&input) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+
+    Ok(0)
+}
+
+struct Repl<'a> {
+    config: &'a Config,
+    cache: &'a Cache,
+    session: Session,
+    project: TempDir,
+    /// プロジェクトの rust-toolchain に書き込んだツールチェイン。
+    toolchain: String,
+    /// プロジェクトの Cargo.toml に書き込んだエディション。
+    edition: String,
+    /// プロジェクトに追加済みの依存クレート。
+    added: BTreeMap<String, Dependency>,
+}
+
+/// これまでに入力された内容。
+#[derive(Default, Clone)]
+struct Session {
+    toolchain: Option<String>,
+    deps: Vec<String>,
+    /// `use` を含むアイテム。
+    items: Vec<String>,
+    /// `fn main` の中の文。
+    statements: Vec<String>,
+    /// これまでに表示した標準出力と標準エラー出力の長さ。
+    stdout_len: usize,
+    stderr_len: usize,
+}
+
+impl<'a> Repl<'a> {
+    fn new(config: &'a Config, cache: &'a Cache) -> Fallible<Repl<'a>> {
+        let session = Session::default();
+        let context = Context::parse(&session.source(""), &env::current_dir()?, config, &[])?;
+        let project = Builder::new().prefix("rustjunk").tempdir()?;
+        crate::create_project(project.path(), &context)?;
+        crate::write_edition(project.path(), &context)?;
+        crate::write_toolchain(project.path(), &context)?;
+
+        Ok(Repl {
+            config,
+            cache,
+            session,
+            project,
+            toolchain: context.toolchain,
+            edition: context.edition,
+            added: BTreeMap::new(),
+        })
+    }
+
+    /// 入力を一つ処理する。終了する場合は `false` を返す。
+    fn eval(&mut self, input: &str) -> Fallible<bool> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(true);
+        }
+
+        if let Some(command) = input.strip_prefix(':') {
+            let (name, arg) = match command.find(char::is_whitespace) {
+                Some(i) => (&command[..i], command[i..].trim()),
+                None => (command, ""),
+            };
+            return self.run_command(name, arg);
+        }
+
+        let mut session = self.session.clone();
+        if is_items(input) {
+            session.items.push(input.to_string());
+            self.try_run(session, "", None)?;
+            return Ok(true);
+        }
+
+        let (statements, tail) = eval::split_tail(input);
+        session.statements.push(statements.to_string());
+        match tail {
+            Some(tail) => {
+                // 末尾の式の値は今回だけ表示し、以降は評価だけする。評価による出力と値の表示を区別できるよう、
+                // 評価してから区切りを出力する。
+                let extra = format!(
+                    "match &(\n{}\n) {{ __rust_runner_tail => {{\nprint!(\"{}\");\n{}\n}} }}",
+                    tail,
+                    VALUE_MARKER,
+                    eval::print_value("*__rust_runner_tail")
+                );
+                let statement = format!("let _ = &(\n{}\n);", tail);
+                self.try_run(session, &extra, Some(statement))?;
+            }
+            None => self.try_run(session, "", None)?,
+        }
+
+        Ok(true)
+    }
+
+    fn run_command(&mut self, name: &str, arg: &str) -> Fallible<bool> {
+        match (name, arg) {
+            ("q", "") | ("quit", "") => return Ok(false),
+            ("help", "") => eprintln!("{}", HELP),
+            ("show", "") => print!("{}", self.session.source("")),
+            ("reset", "") => *self = Repl::new(self.config, self.cache)?,
+            ("dep", spec) if !spec.is_empty() => {
+                Dependency::parse(spec)?;
+                let mut session = self.session.clone();
+                session.deps.push(spec.to_string());
+                self.try_run(session, "", None)?;
+            }
+            ("toolchain", toolchain) if !toolchain.is_empty() => {
+                let mut session = self.session.clone();
+                session.toolchain = Some(toolchain.to_string());
+                self.try_run(session, "", None)?;
+            }
+            ("type", expr) if !expr.is_empty() => {
+                let extra = format!(
+                    "fn __rust_runner_type_name<T: ?Sized>(_: &T) -> &'static str {{ ::std::any::type_name::<T>() }}\nprint!(\"{}\");\nprintln!(\"{{}}\", __rust_runner_type_name(&(\n{}\n)));",
+                    VALUE_MARKER, expr
+                );
+                let session = self.session.clone();
+                self.run(&session, &extra)?;
+            }
+            _ => eprintln!("unknown command: :{} (type :help for help)", name),
+        }
+
+        Ok(true)
+    }
+
+    /// セッションを実行し、成功すればそれに `statement` を加えたものを新しいセッションにする。
+    fn try_run(
+        &mut self,
+        mut session: Session,
+        extra: &str,
+        statement: Option<String>,
+    ) -> Fallible<()> {
+        if let Some((stdout_len, stderr_len)) = self.run(&session, extra)? {
+            session.statements.extend(statement);
+            session.stdout_len = stdout_len;
+            session.stderr_len = stderr_len;
+            self.session = session;
+        }
+
+        Ok(())
+    }
+
+    /// セッションに `extra` の文を加えたプログラムをビルドして実行し、新しい出力を表示する。
+    ///
+    /// 成功した場合は、`extra` による出力を除いた標準出力と標準エラー出力の長さを返す。
+    fn run(&mut self, session: &Session, extra: &str) -> Fallible<Option<(usize, usize)>> {
+        let content = session.source(extra);
+        let context = Context::parse(&content, &env::current_dir()?, self.config, &[])?;
+        let project_dir = self.project.path();
+
+        if context.toolchain != self.toolchain {
+            crate::write_toolchain(project_dir, &context)?;
+            self.toolchain = context.toolchain.clone();
+        }
+        if context.edition != self.edition {
+            crate::write_edition(project_dir, &context)?;
+            self.edition = context.edition.clone();
+        }
+
+        // 新しく参照されたクレートだけを追加する
+        let new_dependencies: Vec<&Dependency> = context
+            .dependencies
+            .values()
+            .filter(|dependency| self.added.get(&dependency.name) != Some(dependency))
+            .collect();
+        crate::add_dependencies(project_dir, &context, new_dependencies, false)?;
+        self.added = context.dependencies.clone();
+
+        crate::write_source(project_dir, &content)?;
+        let binary = match crate::build_and_extract(project_dir, &context, self.cache, true) {
+            Ok(binary) => binary,
+            // エラーはビルド時に表示されている
+            Err(_) => return Ok(None),
+        };
+
+        let output = Command::new(binary).stdin(Stdio::null()).output()?;
+        let (program_stdout, value) = split_at_marker(&output.stdout);
+        let new_stdout = program_stdout.get(session.stdout_len..).unwrap_or(&[]);
+        let new_stderr = output.stderr.get(session.stderr_len..).unwrap_or(&[]);
+        stdout().write_all(new_stdout)?;
+        stdout().write_all(value)?;
+        stdout().flush()?;
+        io::stderr().write_all(new_stderr)?;
+
+        if !output.status.success() {
+            eprintln!("the program exited with {}", output.status);
+            return Ok(None);
+        }
+
+        Ok(Some((program_stdout.len(), output.stderr.len())))
+    }
+}
+
+impl Session {
+    /// セッションの内容からプログラムを作る。`extra` は `fn main` の末尾に加える。
+    fn source(&self, extra: &str) -> String {
+        let mut source = String::new();
+        if let Some(toolchain) = &self.toolchain {
+            source.push_str(&format!("// rust-runner: toolchain={}\n", toolchain));
+        }
+        for dep in &self.deps {
+            source.push_str(&format!("// rust-runner: dep={}\n", dep));
+        }
+
+        // 以前の入力による警告が毎回表示されないよう、警告はすべて抑制する
+        source.push_str("#![allow(warnings)]\n");
+        for item in &self.items {
+            source.push_str(item);
+            source.push('\n');
+        }
+
+        source.push_str("fn main() {\n");
+        for statement in &self.statements {
+            source.push_str(statement);
+            source.push('\n');
+        }
+        source.push_str(extra);
+        source.push_str("\n}\n");
+
+        source
+    }
+}
+
+/// 入力がアイテム (`use` や関数・構造体の定義など) だけからなるか。
+fn is_items(input: &str) -> bool {
+    match syn::parse_file(input) {
+        // `println!(...);` などもアイテムとして解釈できるが、`macro_rules!` 以外のマクロは文として扱う
+        Ok(file) => file.items.iter().all(|item| match item {
+            syn::Item::Macro(item) => item.ident.is_some(),
+            _ => true,
+        }),
+        Err(_) => false,
+    }
+}
+
+/// 出力を、末尾の式の値の区切りより前と後に分ける。
+fn split_at_marker(output: &[u8]) -> (&[u8], &[u8]) {
+    let marker = VALUE_MARKER.as_bytes();
+    match output
+        .windows(marker.len())
+        .position(|window| window == marker)
+    {
+        Some(i) => (&output[..i], &output[i + marker.len()..]),
+        None => (output, &[]),
+    }
+}
+
+/// 入力を一つ読む。括弧が閉じていない間は続けて次の行を読む。入力が終わった場合は `None` を返す。
+fn read_input() -> Fallible<Option<String>> {
+    let mut input = String::new();
+    loop {
+        eprint!("{}", if input.is_empty() { ">> " } else { ".. " });
+        io::stderr().flush()?;
+
+        let mut line = String::new();
+        if stdin().read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(if input.is_empty() { None } else { Some(input) });
+        }
+        input.push_str(&line);
+
+        if bracket_depth(&input) <= 0 {
+            return Ok(Some(input));
+        }
+    }
+}
+
+/// 文字列リテラルの外にある開き括弧と閉じ括弧の数の差を数える。
+fn bracket_depth(input: &str) -> i32 {
+    let mut depth = 0;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    depth
+}
@@ -0,0 +1,195 @@
+//! `rust-runner stress` による愚直解との比較テスト。
+//!
+//! 解答、愚直解、入力の生成器の三つのスクリプトを通常の実行と同じようにビルドし、シード値を変えながら
+//! 生成器の出力を解答と愚直解に与えて出力を比べる。最初に食い違った入力をファイルに保存し、二つの出力の
+//! 差分を表示して終了する。
+//!
+//! 生成器にはシード値を最初の引数として渡す。解答のスクリプトより後の引数はシード値の後に続けて渡すので、
+//! 入力の大きさなどの指定に使える。
+
+use crate::config::Config;
+use crate::judge::{self, Comparator};
+use crate::limit::{Limits, Termination};
+use crate::{Args, Context, SourceFile};
+use failure::{bail, Fallible};
+use std::fs;
+use std::io::prelude::*;
+use std::io::stderr;
+use std::iter;
+use std::path::PathBuf;
+use tempfile::{Builder, TempDir};
+
+const USAGE: &str = "usage: rust-runner stress --brute <source> --gen <source> [--seed <n>] [--iterations <n>] [--compare exact|whitespace|float[=<eps>]] [--save <path>] [<flags>...] <source> [<generator args>...]";
+
+/// 食い違った入力を保存する既定のファイル。
+const DEFAULT_SAVE_PATH: &str = "stress-failed.in";
+
+/// ビルドしたスクリプト。
+struct Program {
+    /// 実行ファイルを置いているディレクトリ。実行ファイルを使い終わるまで削除しないよう保持する。
+    _dir: TempDir,
+    binary: PathBuf,
+    limits: Limits,
+}
+
+/// `rust-runner stress` を実行する。
+pub fn command(args: &[&str], mut config: Config) -> Fallible<i32> {
+    let mut brute = None;
+    let mut generator = None;
+    let mut first_seed = 1;
+    let mut iterations = None;
+    let mut comparator = Comparator::Whitespace;
+    let mut save_path = PathBuf::from(DEFAULT_SAVE_PATH);
+    let mut rest = args;
+    loop {
+        match rest {
+            ["--brute", path, tail @ ..] => {
+                brute = Some(PathBuf::from(path));
+                rest = tail;
+            }
+            ["--gen", path, tail @ ..] => {
+                generator = Some(PathBuf::from(path));
+                rest = tail;
+            }
+            ["--seed", n, tail @ ..] => {
+                first_seed = parse_number(n)?;
+                rest = tail;
+            }
+            ["--iterations", n, tail @ ..] => {
+                iterations = Some(parse_number(n)?);
+                rest = tail;
+            }
+            ["--compare", mode, tail @ ..] => {
+                comparator = Comparator::parse(mode)?;
+                rest = tail;
+            }
+            ["--save", path, tail @ ..] => {
+                save_path = PathBuf::from(path);
+                rest = tail;
+            }
+            _ => break,
+        }
+    }
+    let (brute, generator) = match (brute, generator) {
+        (Some(brute), Some(generator)) => (brute, generator),
+        _ => bail!("--brute and --gen are required.\n{}", USAGE),
+    };
+
+    // 残りは通常の実行と同じようにパースする。コマンドラインのオプションは三つのスクリプトすべてに適用する。
+    let args = Args::parse_timing_subcommand_args("stress", rest, &mut config)?;
+    if let SourceFile::Stdin = args.source_file {
+        bail!("a source file is required.\n{}", USAGE);
+    }
+
+    let solution = build(&args.source_file, &args, &config)?;
+    let brute = build(&SourceFile::Path(brute), &args, &config)?;
+    let generator = build(&SourceFile::Path(generator), &args, &config)?;
+
+    let mut stderr = stderr();
+    // 回数を指定しなければ、食い違いが見つかるまで続ける
+    let end_seed = iterations.map_or(u64::MAX, |iterations| first_seed.saturating_add(iterations));
+    for seed in first_seed..end_seed {
+        write!(stderr, "\rseed {}", seed)?;
+        stderr.flush()?;
+
+        let generator_args: Vec<String> = iter::once(seed.to_string())
+            .chain(args.program_args.iter().cloned())
+            .collect();
+        let (outcome, input, generator_stderr) =
+            crate::run_with_input(&generator.binary, &generator_args, &[], &generator.limits)?;
+        if !is_success(outcome.termination) {
+            writeln!(stderr)?;
+            print_stderr(&generator_stderr);
+            bail!(
+                "the generator failed on seed {}: {}.",
+                seed,
+                outcome.termination
+            );
+        }
+
+        let (brute_outcome, expected, brute_stderr) =
+            crate::run_with_input(&brute.binary, &[], &input, &brute.limits)?;
+        if !is_success(brute_outcome.termination) {
+            writeln!(stderr)?;
+            fs::write(&save_path, &input)?;
+            print_stderr(&brute_stderr);
+            bail!(
+                "the brute-force solution failed on seed {}: {}. the input is saved to {}.",
+                seed,
+                brute_outcome.termination,
+                save_path.display()
+            );
+        }
+
+        let (outcome, output, solution_stderr) =
+            crate::run_with_input(&solution.binary, &[], &input, &solution.limits)?;
+        let failure = if !is_success(outcome.termination) {
+            outcome.termination.to_string()
+        } else if !comparator.matches(&output, &expected) {
+            "produced a different output".to_string()
+        } else {
+            continue;
+        };
+
+        writeln!(stderr)?;
+        fs::write(&save_path, &input)?;
+        println!(
+            "seed {}: the solution {}. the input is saved to {}.",
+            seed,
+            failure,
+            save_path.display()
+        );
+        println!("  input:");
+        for line in String::from_utf8_lossy(&input).lines() {
+            println!("  {}", line);
+        }
+        judge::print_diff(&expected, &output);
+        print_stderr(&solution_stderr);
+        return Ok(1);
+    }
+
+    writeln!(stderr)?;
+    println!("all {} inputs passed.", end_seed - first_seed);
+
+    Ok(0)
+}
+
+/// スクリプトを読み込んでビルドする。
+fn build(source_file: &SourceFile, args: &Args, config: &Config) -> Fallible<Program> {
+    let content = source_file.read_content()?;
+    let context = Context::parse(&content, &source_file.base_dir()?, config, &args.options)?;
+    let dir = Builder::new().prefix("rustjunk").tempdir()?;
+    let binary = crate::prepare_binary(dir.path(), &content, &context, args.no_cache)?;
+
+    Ok(Program {
+        _dir: dir,
+        binary,
+        limits: context.limits,
+    })
+}
+
+fn parse_number(n: &str) -> Fallible<u64> {
+    match n.parse() {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("invalid number: {}", n),
+    }
+}
+
+fn is_success(termination: Termination) -> bool {
+    match termination {
+        Termination::Exited(status) => status.success(),
+        Termination::TimeLimitExceeded | Termination::MemoryLimitExceeded => false,
+    }
+}
+
+/// プログラムの標準エラー出力を字下げして表示する。
+fn print_stderr(stderr: &[u8]) {
+    if stderr.is_empty() {
+        return;
+    }
+
+    println!("  stderr:");
+    for line in String::from_utf8_lossy(stderr).lines() {
+        println!("  {}", line);
+    }
+}
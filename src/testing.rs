@@ -0,0 +1,79 @@
+//! `rust-runner test` によるスクリプト中のテストの実行。
+//!
+//! 通常の実行と同じようにプロジェクトを作り、`cargo test --no-run` でテストをビルドしてから、できた
+//! テストの実行ファイルを直接実行する。スクリプト以降の引数 (テストのフィルタや `--nocapture` など) は
+//! テストの実行ファイルへそのまま渡す。
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::{Args, Context, ProjectError};
+use failure::{bail, Fallible};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::Builder;
+
+/// `rust-runner test [<flags>...] <source> [<test args>...]` を実行する。
+pub fn command(args: &[&str], mut config: Config) -> Fallible<i32> {
+    let args = Args::parse_subcommand_args("test", args, &mut config)?;
+
+    let content = args.source_file.read_content()?;
+    let context = Context::parse(
+        &content,
+        &args.source_file.base_dir()?,
+        &config,
+        &args.options,
+    )?;
+
+    let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+    let cache = Cache::open()?;
+    crate::init_project(tmpdir.path(), &content, &context)?;
+    let binary = build_tests(tmpdir.path(), &context, &cache)?;
+
+    let status = Command::new(binary).args(&args.program_args).status()?;
+
+    Ok(crate::exit_code_of(status))
+}
+
+/// 共有ターゲットディレクトリを使ってテストをビルドし、できたテストの実行ファイルをプロジェクトの
+/// ディレクトリへ取り出してそのパスを返す。
+fn build_tests(project_dir: &Path, context: &Context, cache: &Cache) -> Fallible<PathBuf> {
+    let target_dir = cache.lock_target_dir(&context.toolchain)?;
+
+    // 実行ファイルの場所は JSON で出力される成果物の情報から得る。診断は通常どおり表示する。
+    let output = Command::new("cargo")
+        .arg("test")
+        .arg("--no-run")
+        .arg("--message-format=json-render-diagnostics")
+        .arg("--target-dir")
+        .arg(target_dir.path())
+        .args(crate::profile_args(&context.profile))
+        .current_dir(project_dir)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(ProjectError::BuildFailed.into());
+    }
+
+    let built = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter(|message| message["profile"]["test"] == true)
+        .find_map(|message| message["executable"].as_str().map(PathBuf::from));
+    let built = match built {
+        Some(built) => built,
+        None => bail!("cannot find the test executable."),
+    };
+
+    let binary = project_dir.join(format!(
+        "{}-test{}",
+        crate::PROJECT_NAME,
+        env::consts::EXE_SUFFIX
+    ));
+    fs::copy(&built, &binary)?;
+
+    Ok(binary)
+}
@@ -0,0 +1,207 @@
+//! ツールチェインの情報。
+
+use failure::{bail, Fallible};
+use semver::Version;
+use std::process::Command;
+
+/// 対応しているエディションと、それを使える最初の Rust のバージョン (major, minor)。
+const EDITIONS: &[(&str, (u64, u64))] = &[
+    ("2015", (1, 0)),
+    ("2018", (1, 31)),
+    ("2021", (1, 56)),
+    ("2024", (1, 85)),
+];
+
+/// ツールチェインがインストールされているか確認する。
+///
+/// インストールされていない場合、`install` のときは rustup でインストールする。そうでなければ、
+/// インストールされているものから名前の近いツールチェインを挙げてエラーにする。rustup がない環境では
+/// 確認しない。
+pub fn ensure_installed(
+    toolchain: &str,
+    components: &[String],
+    targets: &[String],
+    install: bool,
+) -> Fallible<()> {
+    let installed = match installed_toolchains() {
+        Some(installed) => installed,
+        None => return Ok(()),
+    };
+
+    // rustup はツールチェイン名にホストのターゲットを付けて表示する
+    let host = host();
+    let strip_host = |name: &str| match &host {
+        Some(host) => name
+            .strip_suffix(host.as_str())
+            .and_then(|name| name.strip_suffix('-'))
+            .map_or(name.to_string(), str::to_string),
+        None => name.to_string(),
+    };
+    let names: Vec<String> = installed.iter().map(|name| strip_host(name)).collect();
+    if installed.iter().chain(&names).any(|name| name == toolchain) {
+        return Ok(());
+    }
+
+    if install {
+        return install_toolchain(toolchain, components, targets);
+    }
+
+    let mut message = format!("toolchain `{}` is not installed.", toolchain);
+    let suggestions: Vec<String> = names
+        .iter()
+        .filter(|name| edit_distance(name, toolchain) <= (toolchain.chars().count() / 3).max(2))
+        .map(|name| format!("`{}`", name))
+        .collect();
+    if !suggestions.is_empty() {
+        message.push_str(&format!(" did you mean {}?", suggestions.join(" or ")));
+    }
+    message.push_str(&format!(
+        " install it with `rustup toolchain install {}` or pass --install-toolchain.",
+        toolchain
+    ));
+
+    bail!("{}", message)
+}
+
+/// rustup でインストールされているツールチェインの名前を得る。rustup がなければ `None` を返す。
+fn installed_toolchains() -> Option<Vec<String>> {
+    let output = Command::new("rustup")
+        .arg("toolchain")
+        .arg("list")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // 各行は `stable-x86_64-unknown-linux-gnu (active, default)` のような形をしている
+    let toolchains = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect();
+
+    Some(toolchains)
+}
+
+/// rustup でツールチェインを、指定したコンポーネントとターゲットとともにインストールする。
+fn install_toolchain(toolchain: &str, components: &[String], targets: &[String]) -> Fallible<()> {
+    eprintln!("installing toolchain `{}`", toolchain);
+    let mut command = Command::new("rustup");
+    command
+        .arg("toolchain")
+        .arg("install")
+        .arg(toolchain)
+        .arg("--profile")
+        .arg("minimal")
+        .arg("--no-self-update");
+    for component in components {
+        command.arg("--component").arg(component);
+    }
+    for target in targets {
+        command.arg("--target").arg(target);
+    }
+    if !command.status()?.success() {
+        bail!("failed to install toolchain `{}`.", toolchain);
+    }
+
+    Ok(())
+}
+
+/// 既定の rustc のホストのターゲットを得る。
+fn host() -> Option<String> {
+    let output = Command::new("rustc").arg("-vV").output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let host = output.lines().find_map(|line| line.strip_prefix("host:"))?;
+
+    Some(host.trim().to_string())
+}
+
+/// 二つの文字列の編集距離 (レーベンシュタイン距離) を求める。
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == *cb { 0 } else { 1 };
+            curr.push(substitute.min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+
+    prev[b.len()]
+}
+
+/// 指定したツールチェインの `rustc -vV` の出力を得る。
+pub fn rustc_version(toolchain: &str) -> Fallible<String> {
+    // rustup 経由でツールチェインを指定する。rustup がない環境では指定なしで実行する。
+    let output = Command::new("rustc")
+        .arg(format!("+{}", toolchain))
+        .arg("-vV")
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        _ => Command::new("rustc").arg("-vV").output()?,
+    };
+    if !output.status.success() {
+        bail!("failed to get the version of rustc.");
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// 指定したツールチェインの rustc のバージョンを得る。
+pub fn release(toolchain: &str) -> Fallible<Version> {
+    let version = rustc_version(toolchain)?;
+    let release = version
+        .lines()
+        .find_map(|line| line.strip_prefix("release:"))
+        .and_then(|release| Version::parse(release.trim()).ok());
+    match release {
+        Some(release) => Ok(release),
+        None => bail!("failed to parse the version of rustc: {}", version.trim()),
+    }
+}
+
+/// 指定したツールチェインが対応している最新のエディションを得る。
+pub fn latest_edition(toolchain: &str) -> Fallible<String> {
+    let release = release(toolchain)?;
+    let edition = EDITIONS
+        .iter()
+        .rev()
+        .find(|(_, since)| *since <= (release.major, release.minor))
+        .map_or("2015", |(name, _)| name);
+
+    Ok(edition.to_string())
+}
+
+/// エディションが存在し、指定したツールチェインで使えるか確認する。
+pub fn check_edition(edition: &str, toolchain: &str) -> Fallible<()> {
+    let (major, minor) = match EDITIONS.iter().find(|(name, _)| *name == edition) {
+        Some((_, since)) => *since,
+        None => {
+            let editions: Vec<&str> = EDITIONS.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "unknown edition: {} (supported: {})",
+                edition,
+                editions.join(", ")
+            );
+        }
+    };
+
+    // nightly などのプレリリースも同じバージョンの stable と同じエディションを使えるものとする
+    let release = release(toolchain)?;
+    if (release.major, release.minor) < (major, minor) {
+        bail!(
+            "edition {} requires Rust {}.{} or later, but the toolchain `{}` is Rust {}. use a newer toolchain with `toolchain=<toolchain>`.",
+            edition,
+            major,
+            minor,
+            toolchain,
+            release
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! オフラインでのビルドに使う、ローカルに保存したクレート (`cargo vendor` の形式のディレクトリ)。
+//!
+//! オフラインモードでは、生成したプロジェクトの crates.io をこのディレクトリで置き換えてビルドする。
+//! ディレクトリへは `rust-runner vendor add <crate>` でクレートを追加する。
+
+use crate::config::Config;
+use crate::dependency::Dependency;
+use failure::{bail, Fallible};
+use semver::{Version, VersionReq};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::Builder;
+use toml::{Table, Value};
+
+/// ローカルに保存したクレートのディレクトリ。
+pub struct Vendor {
+    dir: PathBuf,
+    /// 保存されているパッケージの名前とバージョン。
+    packages: Vec<(String, Version)>,
+}
+
+impl Vendor {
+    /// 設定で指定された、またはデータディレクトリにあるディレクトリを開く。存在しなければ作成する。
+    pub fn open(config: &Config) -> Fallible<Vendor> {
+        let dir = match (&config.vendor_dir, dirs::data_dir()) {
+            (Some(dir), _) => dir.clone(),
+            (None, Some(data_dir)) => data_dir.join("rust-runner").join("vendor"),
+            (None, None) => bail!("failed to determine the vendor directory."),
+        };
+        fs::create_dir_all(&dir)?;
+
+        let mut packages = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let manifest = entry?.path().join("Cargo.toml");
+            if let Some(package) = read_package(&manifest) {
+                packages.push(package);
+            }
+        }
+        packages.sort();
+
+        Ok(Vendor { dir, packages })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 依存クレートがローカルにあるか確認し、実際のパッケージ名を返す。
+    ///
+    /// パッケージ名の `-` と `_` は区別しない。
+    pub fn resolve(&self, dependency: &Dependency) -> Fallible<String> {
+        let package = dependency.package.as_ref().unwrap_or(&dependency.name);
+        let normalized = package.replace('-', "_");
+        let candidates: Vec<&(String, Version)> = self
+            .packages
+            .iter()
+            .filter(|(name, _)| name.replace('-', "_") == normalized)
+            .collect();
+        if candidates.is_empty() {
+            bail!(
+                "crate `{}` is not available in the vendor directory {}. add it with `rust-runner vendor add {}`.",
+                package,
+                self.dir.display(),
+                package
+            );
+        }
+
+        if let Some(version) = &dependency.version {
+            let req = match VersionReq::parse(version) {
+                Ok(req) => req,
+                Err(e) => bail!("invalid version requirement for `{}`: {}", package, e),
+            };
+            if !candidates.iter().any(|(_, v)| req.matches(v)) {
+                let available: Vec<String> = candidates.iter().map(|(_, v)| v.to_string()).collect();
+                bail!(
+                    "no version of crate `{}` matching `{}` is available in the vendor directory {} (available: {}). add it with `rust-runner vendor add {}@{}`.",
+                    package,
+                    version,
+                    self.dir.display(),
+                    available.join(", "),
+                    package,
+                    version
+                );
+            }
+        }
+
+        Ok(candidates[0].0.clone())
+    }
+
+    /// 依存クレートを (推移的な依存も含めて) ダウンロードして追加する。
+    fn add(&self, dependencies: &[Dependency]) -> Fallible<()> {
+        // 依存クレートを追加したプロジェクトを作り、`cargo vendor` でその依存をすべて保存する
+        let tmpdir = Builder::new().prefix("rustjunk").tempdir()?;
+        let project_dir = tmpdir.path();
+        let success = Command::new("cargo")
+            .arg("init")
+            .arg("--name")
+            .arg("rustrunner-vendor")
+            .current_dir(project_dir)
+            .status()?
+            .success();
+        if !success {
+            bail!("failed to init cargo project.");
+        }
+
+        for dependency in dependencies {
+            let success = Command::new("cargo")
+                .arg("add")
+                .args(dependency.cargo_add_args())
+                .current_dir(project_dir)
+                .status()?
+                .success();
+            if !success {
+                bail!("failed to add crate `{}`.", dependency.name);
+            }
+        }
+
+        let success = Command::new("cargo")
+            .arg("vendor")
+            .arg("--no-delete")
+            .arg("--versioned-dirs")
+            .arg(&self.dir)
+            .current_dir(project_dir)
+            // 設定の案内は rust-runner が行うので表示しない
+            .stdout(Stdio::null())
+            .status()?
+            .success();
+        if !success {
+            bail!("failed to vendor crates.");
+        }
+
+        Ok(())
+    }
+}
+
+/// `rust-runner vendor <subcommand>` を実行する。
+pub fn command(args: &[&str], config: &Config) -> Fallible<i32> {
+    match args {
+        ["add", specs @ ..] if !specs.is_empty() => {
+            let dependencies = specs
+                .iter()
+                .map(|spec| Dependency::parse(spec))
+                .collect::<Fallible<Vec<_>>>()?;
+            let vendor = Vendor::open(config)?;
+            vendor.add(&dependencies)?;
+            eprintln!("vendored into {}", vendor.dir().display());
+            Ok(0)
+        }
+        ["list"] => {
+            for (name, version) in &Vendor::open(config)?.packages {
+                println!("{} {}", name, version);
+            }
+            Ok(0)
+        }
+        [subcommand, ..] if *subcommand != "add" => {
+            bail!("unknown vendor subcommand: {}", subcommand)
+        }
+        _ => bail!("usage: rust-runner vendor add <crate>[@<version>][[<features>]]... | vendor list"),
+    }
+}
+
+/// ネットワークを使わず、crates.io を指定したディレクトリで置き換える `.cargo/config` の設定を返す。
+pub fn cargo_config(dir: &Path) -> String {
+    format!(
+        "[net]\noffline = true\n\n[source.crates-io]\nreplace-with = \"rust-runner-vendor\"\n\n[source.rust-runner-vendor]\ndirectory = \"{}\"\n",
+        dir.display().to_string().escape_default()
+    )
+}
+
+/// `cargo vendor` で保存されたパッケージの Cargo.toml から名前とバージョンを読む。
+fn read_package(manifest: &Path) -> Option<(String, Version)> {
+    let manifest: Table = fs::read_to_string(manifest).ok()?.parse().ok()?;
+    let package = manifest.get("package")?.as_table()?;
+    let name = package.get("name").and_then(Value::as_str)?;
+    let version = package.get("version").and_then(Value::as_str)?;
+
+    Some((name.to_string(), Version::parse(version).ok()?))
+}
@@ -0,0 +1,192 @@
+//! `--watch` によるソースファイルの監視と再実行。
+//!
+//! 生成したプロジェクトを保持したまま、ソースファイルが保存されるたびに `src/main.rs` だけを書き換えて
+//! ビルドし直し、プログラムを実行し直す。依存クレートは追加・変更されたものだけを `cargo add` する。
+//! 埋め込まれた Cargo.toml やエディション、プロファイル、オフラインモードの設定が変わった場合はプロジェクトを作り直す。
+//!
+//! プログラムの実行中にソースファイルが保存された場合は、プログラムを終了させてから実行し直す。時間制限を
+//! 超えた場合もプログラムを終了させる。
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::dependency::Dependency;
+use crate::limit;
+use crate::{Args, Context, OptionType, SourceFile};
+use failure::{bail, Fallible};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::prelude::*;
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::{Builder, TempDir};
+
+/// 実行中のプログラムが終了したか確認する間隔。
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 一度の保存で続けて起きるイベントをまとめるための待ち時間。
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// 保持しているプロジェクト。
+struct Project {
+    dir: TempDir,
+    /// プロジェクトに反映済みの設定。
+    context: Context,
+}
+
+impl Project {
+    /// 新しい設定をこのプロジェクトに反映できるか。依存クレートとツールチェイン以外が変わった場合は
+    /// 作り直す必要がある。
+    fn can_reuse(&self, context: &Context) -> bool {
+        self.context.manifest == context.manifest
+            && self.context.vendor_dir == context.vendor_dir
+            && self.context.dev_dependencies == context.dev_dependencies
+            && self.context.edition == context.edition
+            && self.context.profile == context.profile
+            && self.context.opt_level == context.opt_level
+            && self.context.rustflags == context.rustflags
+    }
+}
+
+/// ソースファイルを監視し、保存されるたびにビルドして実行する。Ctrl-C で止めるまで戻らない。
+pub fn run(path: &Path, args: &Args, config: &Config) -> Fallible<i32> {
+    let path = path.canonicalize()?;
+    let (dir, file_name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file_name)) => (dir, file_name),
+        _ => bail!("cannot watch {}", path.display()),
+    };
+
+    // エディタによってはファイルを置き換えて保存するので、ファイルではなく親ディレクトリを監視する
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    let cache = Cache::open()?;
+    let mut project = None;
+    loop {
+        clear_screen()?;
+        eprintln!("watching {}. press Ctrl-C to stop.", path.display());
+        let mut child = match build(&path, &args.options, config, &cache, &mut project) {
+            Ok(binary) => {
+                let limits = project.as_ref().map(|project| project.context.limits);
+                let mut command = Command::new(binary);
+                command.args(&args.program_args);
+                limit::apply(&mut command, &limits.unwrap_or_default());
+                Some((
+                    command.spawn()?,
+                    Instant::now(),
+                    limits.and_then(|limits| limits.time),
+                ))
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                None
+            }
+        };
+
+        // ソースファイルが保存されるまで待つ。その間にプログラムが終了したら終了状態を表示する。
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) if is_saved(&event, file_name) => break,
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(e)) => return Err(e.into()),
+                Err(RecvTimeoutError::Disconnected) => bail!("stopped watching the source file."),
+            }
+
+            if let Some((running, start, time_limit)) = &mut child {
+                if let Some(status) = running.try_wait()? {
+                    eprintln!(
+                        "\nexited with code {}. waiting for changes...",
+                        crate::exit_code_of(status)
+                    );
+                    child = None;
+                } else if time_limit.is_some_and(|time_limit| start.elapsed() > time_limit) {
+                    let _ = running.kill();
+                    running.wait()?;
+                    eprintln!("\ntime limit exceeded. waiting for changes...");
+                    child = None;
+                }
+            }
+        }
+
+        if let Some((mut child, _, _)) = child {
+            // すでに終了している場合は失敗するが、いずれにしても終了を待てばよい
+            let _ = child.kill();
+            child.wait()?;
+        }
+
+        thread::sleep(DEBOUNCE);
+        while rx.try_recv().is_ok() {}
+    }
+}
+
+/// ソースファイルを読み込んでプロジェクトに反映し、ビルドして実行ファイルのパスを返す。
+fn build(
+    path: &Path,
+    overrides: &[(OptionType, String)],
+    config: &Config,
+    cache: &Cache,
+    project: &mut Option<Project>,
+) -> Fallible<PathBuf> {
+    let content = fs::read_to_string(path)?;
+    let base_dir = SourceFile::Path(path.to_path_buf()).base_dir()?;
+    let context = Context::parse(&content, &base_dir, config, overrides)?;
+
+    let project = match project {
+        Some(project) if project.can_reuse(&context) => {
+            let project_dir = project.dir.path();
+
+            // 使われなくなった依存クレートは残しておいても害はないので、追加・変更されたものだけを反映する
+            let changed: Vec<&Dependency> = context
+                .dependencies
+                .values()
+                .filter(|dependency| {
+                    project.context.dependencies.get(&dependency.name) != Some(dependency)
+                })
+                .collect();
+            crate::add_dependencies(project_dir, &context, changed, false)?;
+            if (&context.toolchain, &context.components, &context.targets)
+                != (
+                    &project.context.toolchain,
+                    &project.context.components,
+                    &project.context.targets,
+                )
+            {
+                crate::write_toolchain(project_dir, &context)?;
+            }
+            crate::write_source(project_dir, &content)?;
+
+            project.context = context;
+            project
+        }
+        _ => {
+            let dir = Builder::new().prefix("rustjunk").tempdir()?;
+            crate::init_project(dir.path(), &content, &context)?;
+            project.insert(Project { dir, context })
+        }
+    };
+
+    crate::build_and_extract(project.dir.path(), &project.context, cache, false)
+}
+
+/// イベントがソースファイルの保存によるものか。
+fn is_saved(event: &Event, file_name: &OsStr) -> bool {
+    let is_access = matches!(event.kind, EventKind::Access(_));
+    !is_access
+        && event
+            .paths
+            .iter()
+            .any(|path| path.file_name() == Some(file_name))
+}
+
+/// 端末の画面を消去してカーソルを左上に移動する。
+fn clear_screen() -> Fallible<()> {
+    print!("\x1b[2J\x1b[H");
+    stdout().flush()?;
+
+    Ok(())
+}